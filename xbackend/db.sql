@@ -1,16 +1,355 @@
-CREATE TABLE users (
+-- Applied by the server on every start. Each statement only creates what is
+-- missing, so databases made by an older version are brought up to date
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE EXTENSION IF NOT EXISTS cube;
+CREATE EXTENSION IF NOT EXISTS earthdistance;
+
+CREATE TABLE IF NOT EXISTS users (
     username VARCHAR(255) PRIMARY KEY,
     password VARCHAR(255) NOT NULL,
-    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    -- Admins can create invite codes
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Total size of the user's files, unlimited when NULL
+    quota_bytes BIGINT
+);
+
+-- Columns added since the first version of the table
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN IF NOT EXISTS quota_bytes BIGINT;
+
+-- Single-use codes for registering when `REGISTRATION=invite`, only a hash is kept
+CREATE TABLE IF NOT EXISTS invite_codes (
+    code_hash VARCHAR(64) PRIMARY KEY,
+    -- NULL for codes made with the admin command
+    created_by VARCHAR(255) REFERENCES users(username) ON DELETE SET NULL ON UPDATE CASCADE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP,
+    used_by VARCHAR(255) REFERENCES users(username) ON DELETE SET NULL ON UPDATE CASCADE,
+    used_at TIMESTAMP
+);
+
+-- A login on one device, kept alive by rotating its refresh token
+CREATE TABLE IF NOT EXISTS sessions (
+    id BIGSERIAL PRIMARY KEY,
+    username VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    device_name VARCHAR(255),
+    -- Only hashes are kept, the replaced token is remembered so a replayed
+    -- one can end the session
+    refresh_hash VARCHAR(64) NOT NULL UNIQUE,
+    previous_refresh_hash VARCHAR(64) UNIQUE,
+    -- When refresh_hash replaced previous_refresh_hash
+    rotated_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL
 );
 
-CREATE TABLE images (
+CREATE INDEX IF NOT EXISTS sessions_username_idx ON sessions (username);
+
+CREATE TABLE IF NOT EXISTS images (
     hash VARCHAR(64) PRIMARY KEY,
     extension VARCHAR(10),
-    owner VARCHAR(255) REFERENCES users(username),
+    owner VARCHAR(255) REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
     image_name VARCHAR(255),
     created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     modified_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     longitude DOUBLE PRECISION,
-    latitude DOUBLE PRECISION
-);
\ No newline at end of file
+    latitude DOUBLE PRECISION,
+    media_type VARCHAR(16) NOT NULL DEFAULT 'image',
+    width INTEGER,
+    height INTEGER,
+    taken_at TIMESTAMP,
+    camera_model VARCHAR(255),
+    -- NULL for images uploaded before sizes were recorded
+    size_bytes BIGINT,
+    description TEXT,
+    -- Manual edits, kept apart from the values extracted from the file
+    taken_at_override TIMESTAMP,
+    -- Last metadata edit through the API, modified_at stays the file's time on the client
+    edited_at TIMESTAMP,
+    -- Minutes east of UTC recorded with taken_at. NULL when the camera didn't
+    -- say, taken_at is then its local time stored as if it were UTC
+    taken_at_offset SMALLINT,
+    manual_latitude DOUBLE PRECISION,
+    manual_longitude DOUBLE PRECISION,
+    -- Set while the image is in the trash
+    deleted_at TIMESTAMP,
+    favorite BOOLEAN NOT NULL DEFAULT FALSE,
+    rating SMALLINT CHECK (rating BETWEEN 1 AND 5),
+    -- Resolved from the effective location by the geocoder, NULL until then
+    country VARCHAR(255),
+    region VARCHAR(255),
+    city VARCHAR(255),
+    geocoded_at TIMESTAMP,
+    -- Capture time used for sorting and date filters
+    captured_at TIMESTAMP GENERATED ALWAYS AS (COALESCE(taken_at_override, taken_at, created_at)) STORED,
+    -- Location used for geographic queries, a manual location wins over EXIF
+    effective_latitude DOUBLE PRECISION GENERATED ALWAYS AS (COALESCE(manual_latitude, latitude)) STORED,
+    effective_longitude DOUBLE PRECISION GENERATED ALWAYS AS (COALESCE(manual_longitude, longitude)) STORED,
+    CONSTRAINT images_manual_location_check
+        CHECK ((manual_latitude IS NULL) = (manual_longitude IS NULL)),
+    UNIQUE (hash, owner)
+);
+
+-- Columns added since the first version of the table
+ALTER TABLE images
+    ADD COLUMN IF NOT EXISTS media_type VARCHAR(16) NOT NULL DEFAULT 'image',
+    ADD COLUMN IF NOT EXISTS width INTEGER,
+    ADD COLUMN IF NOT EXISTS height INTEGER,
+    ADD COLUMN IF NOT EXISTS taken_at TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS camera_model VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS size_bytes BIGINT,
+    ADD COLUMN IF NOT EXISTS description TEXT,
+    ADD COLUMN IF NOT EXISTS taken_at_override TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS edited_at TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS taken_at_offset SMALLINT,
+    ADD COLUMN IF NOT EXISTS manual_latitude DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS manual_longitude DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS favorite BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN IF NOT EXISTS rating SMALLINT CHECK (rating BETWEEN 1 AND 5),
+    ADD COLUMN IF NOT EXISTS country VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS region VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS city VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS geocoded_at TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS captured_at TIMESTAMP
+        GENERATED ALWAYS AS (COALESCE(taken_at_override, taken_at, created_at)) STORED,
+    ADD COLUMN IF NOT EXISTS effective_latitude DOUBLE PRECISION
+        GENERATED ALWAYS AS (COALESCE(manual_latitude, latitude)) STORED,
+    ADD COLUMN IF NOT EXISTS effective_longitude DOUBLE PRECISION
+        GENERATED ALWAYS AS (COALESCE(manual_longitude, longitude)) STORED;
+
+-- Named like the constraint of a new table, so it is skipped there
+CREATE UNIQUE INDEX IF NOT EXISTS images_hash_owner_key ON images (hash, owner);
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'images_manual_location_check') THEN
+        ALTER TABLE images ADD CONSTRAINT images_manual_location_check
+            CHECK ((manual_latitude IS NULL) = (manual_longitude IS NULL));
+    END IF;
+
+    -- Deleting or renaming a user used to be refused while they had images
+    IF EXISTS (SELECT 1 FROM pg_constraint
+               WHERE conname = 'images_owner_fkey' AND confdeltype <> 'c') THEN
+        ALTER TABLE images DROP CONSTRAINT images_owner_fkey,
+            ADD CONSTRAINT images_owner_fkey FOREIGN KEY (owner)
+                REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE;
+    END IF;
+END
+$$;
+
+CREATE INDEX IF NOT EXISTS images_owner_captured_at_idx ON images (owner, captured_at DESC, hash DESC);
+CREATE INDEX IF NOT EXISTS images_owner_rating_idx ON images (owner, (COALESCE(rating, 0)) DESC, hash DESC);
+CREATE INDEX IF NOT EXISTS images_owner_favorite_idx ON images (owner, captured_at DESC) WHERE favorite;
+CREATE INDEX IF NOT EXISTS images_name_trgm_idx ON images USING GIN (image_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS images_camera_model_trgm_idx ON images USING GIN (camera_model gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS images_location_idx ON images USING GIST (point(effective_longitude, effective_latitude))
+    WHERE effective_latitude IS NOT NULL;
+CREATE INDEX IF NOT EXISTS images_place_idx ON images (owner, country, region, city);
+CREATE INDEX IF NOT EXISTS images_geocode_pending_idx ON images (created_at)
+    WHERE geocoded_at IS NULL AND effective_latitude IS NOT NULL;
+CREATE INDEX IF NOT EXISTS images_earth_idx ON images USING GIST (ll_to_earth(effective_latitude, effective_longitude))
+    WHERE effective_latitude IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS devices (
+    id BIGSERIAL PRIMARY KEY,
+    owner VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    platform VARCHAR(64),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMP,
+    last_sync_at TIMESTAMP,
+    last_sync_status VARCHAR(16),
+    last_sync_cursor BIGINT,
+    -- Last sync that succeeded, last_sync_at is any outcome
+    last_success_at TIMESTAMP,
+    UNIQUE (id, owner)
+);
+
+-- Long-lived tokens for sync clients, each acting for one device. Only a hash
+-- of the token is kept, the token itself is shown once
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id BIGSERIAL PRIMARY KEY,
+    owner VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    device_id BIGINT NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP,
+    revoked_at TIMESTAMP,
+    FOREIGN KEY (device_id, owner) REFERENCES devices(id, owner) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS api_tokens_owner_idx ON api_tokens (owner);
+
+-- Where an uploaded file lives on the device it came from
+CREATE TABLE IF NOT EXISTS image_sources (
+    device_id BIGINT NOT NULL,
+    relative_path VARCHAR(1024) NOT NULL,
+    owner VARCHAR(255) NOT NULL,
+    hash VARCHAR(64) NOT NULL,
+    source_folder VARCHAR(1024),
+    uploaded_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (device_id, relative_path),
+    FOREIGN KEY (device_id, owner) REFERENCES devices(id, owner) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (hash, owner) REFERENCES images(hash, owner) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS image_sources_hash_idx ON image_sources (hash);
+
+CREATE TABLE IF NOT EXISTS albums (
+    id BIGSERIAL PRIMARY KEY,
+    owner VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    cover_hash VARCHAR(64),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    modified_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (id, owner)
+);
+
+CREATE INDEX IF NOT EXISTS albums_owner_idx ON albums (owner);
+
+-- Users the owner shared the album with, contributors can add their own images
+CREATE TABLE IF NOT EXISTS album_members (
+    album_id BIGINT NOT NULL REFERENCES albums(id) ON DELETE CASCADE,
+    username VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    role VARCHAR(16) NOT NULL CHECK (role IN ('contributor', 'viewer')),
+    added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (album_id, username)
+);
+
+CREATE INDEX IF NOT EXISTS album_members_username_idx ON album_members (username);
+
+-- `owner` is the owner of the image, the album owner or a contributor
+CREATE TABLE IF NOT EXISTS album_images (
+    album_id BIGINT NOT NULL REFERENCES albums(id) ON DELETE CASCADE,
+    owner VARCHAR(255) NOT NULL,
+    hash VARCHAR(64) NOT NULL,
+    position INTEGER NOT NULL,
+    added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (album_id, hash),
+    FOREIGN KEY (hash, owner) REFERENCES images(hash, owner) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS album_images_hash_idx ON album_images (hash);
+
+-- The cover has to be a member and is cleared when it leaves the album
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'albums_cover_hash_fkey') THEN
+        ALTER TABLE albums ADD CONSTRAINT albums_cover_hash_fkey FOREIGN KEY (id, cover_hash)
+            REFERENCES album_images(album_id, hash) ON DELETE SET NULL (cover_hash);
+    END IF;
+END
+$$;
+
+-- The owner's whole library is readable by the partner, optionally only
+-- images captured from `since` on
+CREATE TABLE IF NOT EXISTS partner_shares (
+    owner VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    partner VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    since TIMESTAMP,
+    -- Chosen by the partner, shows the shared images in their own timeline
+    in_timeline BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (owner, partner),
+    CHECK (owner <> partner)
+);
+
+CREATE INDEX IF NOT EXISTS partner_shares_partner_idx ON partner_shares (partner);
+
+CREATE TABLE IF NOT EXISTS share_links (
+    id BIGSERIAL PRIMARY KEY,
+    owner VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    -- Only a hash of the token is kept, the token itself is shown once
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    image_hash VARCHAR(64),
+    album_id BIGINT,
+    password VARCHAR(255),
+    allow_download BOOLEAN NOT NULL DEFAULT TRUE,
+    expires_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    revoked_at TIMESTAMP,
+    -- A link shares exactly one image or one album
+    CHECK ((image_hash IS NULL) <> (album_id IS NULL)),
+    FOREIGN KEY (image_hash, owner) REFERENCES images(hash, owner) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (album_id, owner) REFERENCES albums(id, owner) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS share_links_owner_idx ON share_links (owner);
+
+-- Photos grouped automatically by capture time and location, recomputed by
+-- the event worker
+CREATE TABLE IF NOT EXISTS events (
+    id BIGSERIAL PRIMARY KEY,
+    owner VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    title VARCHAR(255) NOT NULL,
+    place VARCHAR(255),
+    starts_at TIMESTAMP NOT NULL,
+    ends_at TIMESTAMP NOT NULL,
+    cover_hash VARCHAR(64),
+    computed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS events_owner_starts_at_idx ON events (owner, starts_at DESC);
+
+-- Not tied to images, rows of deleted images go away when the event is recomputed
+CREATE TABLE IF NOT EXISTS event_images (
+    event_id BIGINT NOT NULL REFERENCES events(id) ON DELETE CASCADE,
+    hash VARCHAR(64) NOT NULL,
+    PRIMARY KEY (event_id, hash)
+);
+
+CREATE INDEX IF NOT EXISTS event_images_hash_idx ON event_images (hash);
+
+-- Journal cursor the event worker has processed each user's changes up to
+CREATE TABLE IF NOT EXISTS event_cursors (
+    owner VARCHAR(255) PRIMARY KEY REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    last_cursor BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS image_tags (
+    hash VARCHAR(64) NOT NULL,
+    owner VARCHAR(255) NOT NULL,
+    tag VARCHAR(64) NOT NULL,
+    PRIMARY KEY (hash, tag),
+    FOREIGN KEY (hash, owner) REFERENCES images(hash, owner) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS image_tags_owner_tag_idx ON image_tags (owner, tag);
+
+CREATE TABLE IF NOT EXISTS image_changes (
+    id BIGSERIAL PRIMARY KEY,
+    owner VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    hash VARCHAR(64) NOT NULL,
+    kind VARCHAR(16) NOT NULL,
+    changed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    -- Transaction that wrote the entry. Ids are taken before commit and can
+    -- become visible out of order, so readers page on this instead and stop
+    -- at the oldest transaction still running
+    xact_id BIGINT NOT NULL DEFAULT pg_current_xact_id()::TEXT::BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS image_changes_owner_xact_id_idx ON image_changes (owner, xact_id, id);
+
+-- Pushes every journal entry to listening servers once its transaction commits
+CREATE OR REPLACE FUNCTION notify_image_change() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('image_changes', json_build_object(
+        'cursor', NEW.xact_id,
+        'owner', NEW.owner,
+        'hash', NEW.hash,
+        'kind', NEW.kind,
+        'changed_at', NEW.changed_at AT TIME ZONE 'UTC'
+    )::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER image_changes_notify
+    AFTER INSERT ON image_changes
+    FOR EACH ROW EXECUTE FUNCTION notify_image_change();
@@ -0,0 +1,267 @@
+use crate::db::{
+    UserError, create_invite, create_user, delete_user, get_user_summaries, rename_user, set_admin,
+    set_password, set_quota,
+};
+use crate::routes::{valid_password, valid_username};
+use crate::secret::{generate_token, hash_token};
+use crate::types::UserCredentials;
+use sqlx::PgPool;
+use std::io::{BufRead, IsTerminal};
+use std::{env, path::PathBuf};
+
+const USAGE: &str = "\
+Usage: backend admin <command>
+
+Commands:
+  create <username> [--admin]         create a user, the password is read from stdin
+  delete <username>                   delete a user with all their images
+  list                                list users and their storage
+  rename <username> <new-username>    rename a user
+  reset-password <username>           set a new password, read from stdin
+  set-quota <username> <size|none>    limit the storage of a user, e.g. 20G or 500M
+  invite [--expires-in-days <days>]   create a single-use invite code";
+
+const MAX_INVITE_DAYS: i64 = 3650;
+
+/// Run `backend admin ...`, returns the process exit code
+pub async fn run(pool: &PgPool, args: &[String]) -> i32 {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args.as_slice() {
+        ["create", username] => create(pool, username, false).await,
+        ["create", username, "--admin"] => create(pool, username, true).await,
+        ["delete", username] => delete(pool, username).await,
+        ["list"] => list(pool).await,
+        ["rename", username, new_username] => rename(pool, username, new_username).await,
+        ["reset-password", username] => reset_password(pool, username).await,
+        ["set-quota", username, size] => quota(pool, username, size).await,
+        ["invite"] => invite(pool, None).await,
+        ["invite", "--expires-in-days", days] => match days.parse::<i64>() {
+            Ok(days) if (1..=MAX_INVITE_DAYS).contains(&days) => invite(pool, Some(days)).await,
+            _ => Err(format!("invalid number of days: {days}")),
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            return 2;
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(message) => {
+            eprintln!("Error: {message}");
+            1
+        }
+    }
+}
+
+fn database_error(e: sqlx::Error) -> String {
+    format!("database error: {e}")
+}
+
+fn user_error(e: UserError) -> String {
+    match e {
+        UserError::UsernameExists => "username is taken".to_string(),
+        e => format!("{e:?}"),
+    }
+}
+
+/// Prompt without echo on a terminal, otherwise read a line so the password
+/// can also be piped in
+fn read_password(username: &str) -> Result<String, String> {
+    let password = if std::io::stdin().is_terminal() {
+        rpassword::prompt_password(format!("Password for {username}: "))
+            .map_err(|e| format!("could not read the password: {e}"))?
+    } else {
+        let mut password = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut password)
+            .map_err(|e| format!("could not read the password: {e}"))?;
+        password.trim_end_matches(['\r', '\n']).to_string()
+    };
+
+    if !valid_password(username, &password) {
+        return Err("the password needs 10 to 128 characters and can't be the username".into());
+    }
+
+    Ok(password)
+}
+
+async fn create(pool: &PgPool, username: &str, admin: bool) -> Result<(), String> {
+    if !valid_username(username) {
+        return Err(format!("invalid username: {username}"));
+    }
+
+    let credentials = UserCredentials {
+        username: username.to_string(),
+        password: read_password(username)?,
+    };
+
+    create_user(pool, &credentials, None)
+        .await
+        .map_err(user_error)?;
+
+    if admin {
+        set_admin(pool, username, true)
+            .await
+            .map_err(database_error)?;
+    }
+
+    println!("Created {username}");
+    Ok(())
+}
+
+async fn delete(pool: &PgPool, username: &str) -> Result<(), String> {
+    let files = delete_user(pool, username)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| format!("no such user: {username}"))?;
+
+    // Files can only go once the rows are gone, a leftover file is harmless
+    if let Ok(storage_path) = env::var("IMAGE_STORAGE_PATH") {
+        for file_name in &files {
+            let file_path = PathBuf::from(&storage_path).join(file_name);
+            if let Err(e) = tokio::fs::remove_file(&file_path).await {
+                eprintln!(
+                    "Warning: Could not delete file {}: {:?}",
+                    file_path.display(),
+                    e
+                );
+            }
+        }
+    } else {
+        eprintln!("Warning: IMAGE_STORAGE_PATH not set, image files were left in place");
+    }
+
+    println!("Deleted {username} and {} images", files.len());
+    Ok(())
+}
+
+async fn list(pool: &PgPool) -> Result<(), String> {
+    let users = get_user_summaries(pool).await.map_err(database_error)?;
+
+    println!(
+        "{:<32} {:<5} {:>8} {:>10} {:>10}  CREATED",
+        "USERNAME", "ADMIN", "IMAGES", "USED", "QUOTA"
+    );
+    for user in users {
+        println!(
+            "{:<32} {:<5} {:>8} {:>10} {:>10}  {}",
+            user.username,
+            if user.is_admin { "yes" } else { "no" },
+            user.image_count,
+            format_size(user.used_bytes),
+            user.quota_bytes.map_or("-".to_string(), format_size),
+            user.created_at
+                .map_or("-".to_string(), |t| t.format("%Y-%m-%d %H:%M").to_string()),
+        );
+    }
+
+    Ok(())
+}
+
+async fn rename(pool: &PgPool, username: &str, new_username: &str) -> Result<(), String> {
+    if !valid_username(new_username) {
+        return Err(format!("invalid username: {new_username}"));
+    }
+
+    let renamed = rename_user(pool, username, new_username)
+        .await
+        .map_err(user_error)?;
+
+    if !renamed {
+        return Err(format!("no such user: {username}"));
+    }
+
+    println!("Renamed {username} to {new_username}");
+    Ok(())
+}
+
+async fn reset_password(pool: &PgPool, username: &str) -> Result<(), String> {
+    let password = read_password(username)?;
+
+    let updated = set_password(pool, username, &password)
+        .await
+        .map_err(user_error)?;
+
+    if !updated {
+        return Err(format!("no such user: {username}"));
+    }
+
+    println!("Password of {username} changed, their sessions and API tokens were ended");
+    Ok(())
+}
+
+async fn quota(pool: &PgPool, username: &str, size: &str) -> Result<(), String> {
+    let quota_bytes = match size {
+        "none" => None,
+        size => Some(parse_size(size).ok_or_else(|| format!("invalid size: {size}"))?),
+    };
+
+    let updated = set_quota(pool, username, quota_bytes)
+        .await
+        .map_err(database_error)?;
+
+    if !updated {
+        return Err(format!("no such user: {username}"));
+    }
+
+    match quota_bytes {
+        Some(bytes) => println!("Quota of {username} set to {}", format_size(bytes)),
+        None => println!("Quota of {username} removed"),
+    }
+    Ok(())
+}
+
+async fn invite(pool: &PgPool, expires_in_days: Option<i64>) -> Result<(), String> {
+    let expires_at = expires_in_days.map(|days| chrono::Utc::now() + chrono::Duration::days(days));
+    let code = generate_token();
+
+    create_invite(pool, None, &hash_token(&code), expires_at)
+        .await
+        .map_err(database_error)?;
+
+    println!("{code}");
+    Ok(())
+}
+
+/// Bytes, or a number with a binary K, M, G or T suffix
+fn parse_size(size: &str) -> Option<i64> {
+    let (number, unit) = match size.char_indices().find(|(_, c)| c.is_ascii_alphabetic()) {
+        Some((i, _)) => size.split_at(i),
+        None => (size, ""),
+    };
+
+    let shift = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return None,
+    };
+
+    number
+        .parse::<i64>()
+        .ok()
+        .filter(|n| *n >= 0)
+        .and_then(|n| n.checked_mul(1 << shift))
+}
+
+fn format_size(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes}B")
+    } else {
+        format!("{size:.1}{}", UNITS[unit])
+    }
+}
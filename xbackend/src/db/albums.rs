@@ -0,0 +1,420 @@
+use super::images::ImageRow;
+use crate::types::{Album, AlbumMember, AlbumRole, Image};
+use chrono::NaiveDateTime;
+use sqlx::{PgConnection, PgPool};
+
+struct AlbumRow {
+    id: i64,
+    owner: String,
+    role: String,
+    name: String,
+    cover_hash: Option<String>,
+    image_count: i64,
+    created_at: Option<NaiveDateTime>,
+    modified_at: Option<NaiveDateTime>,
+}
+
+impl From<AlbumRow> for Album {
+    fn from(r: AlbumRow) -> Self {
+        Album {
+            id: r.id,
+            role: AlbumRole::parse(&r.role).unwrap_or(AlbumRole::Viewer),
+            owner: r.owner,
+            name: r.name,
+            cover_hash: r.cover_hash,
+            image_count: r.image_count,
+            created_at: r
+                .created_at
+                .unwrap_or_else(|| chrono::Utc::now().naive_utc())
+                .and_utc(),
+            modified_at: r
+                .modified_at
+                .unwrap_or_else(|| chrono::Utc::now().naive_utc())
+                .and_utc(),
+        }
+    }
+}
+
+pub async fn create_album(pool: &PgPool, owner: &str, name: &str) -> Result<Album, sqlx::Error> {
+    let record = sqlx::query_as!(
+        AlbumRow,
+        r#"
+        INSERT INTO albums (owner, name)
+        VALUES ($1, $2)
+        RETURNING id, owner, 'owner' AS "role!", name, cover_hash, 0::BIGINT AS "image_count!",
+                  created_at, modified_at
+        "#,
+        owner,
+        name
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Album::from(record))
+}
+
+/// Albums the user owns or is a member of
+pub async fn get_albums(pool: &PgPool, username: &str) -> Result<Vec<Album>, sqlx::Error> {
+    let records = sqlx::query_as!(
+        AlbumRow,
+        r#"
+        SELECT a.id, a.owner, a.name, a.cover_hash, a.created_at, a.modified_at,
+               COALESCE(m.role, 'owner') AS "role!",
+               (SELECT COUNT(*)
+                FROM album_images ai
+                JOIN images i ON i.hash = ai.hash
+                WHERE ai.album_id = a.id AND i.deleted_at IS NULL) AS "image_count!"
+        FROM albums a
+        LEFT JOIN album_members m ON m.album_id = a.id AND m.username = $1
+        WHERE a.owner = $1 OR m.username IS NOT NULL
+        ORDER BY a.modified_at DESC
+        "#,
+        username
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(Album::from).collect())
+}
+
+/// The album if the user owns it or is a member, `role` tells which
+pub async fn get_album(
+    pool: &PgPool,
+    id: i64,
+    username: &str,
+) -> Result<Option<Album>, sqlx::Error> {
+    let record = sqlx::query_as!(
+        AlbumRow,
+        r#"
+        SELECT a.id, a.owner, a.name, a.cover_hash, a.created_at, a.modified_at,
+               COALESCE(m.role, 'owner') AS "role!",
+               (SELECT COUNT(*)
+                FROM album_images ai
+                JOIN images i ON i.hash = ai.hash
+                WHERE ai.album_id = a.id AND i.deleted_at IS NULL) AS "image_count!"
+        FROM albums a
+        LEFT JOIN album_members m ON m.album_id = a.id AND m.username = $2
+        WHERE a.id = $1 AND (a.owner = $2 OR m.username IS NOT NULL)
+        "#,
+        id,
+        username
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(Album::from))
+}
+
+/// Rename the album and/or change its cover, `Some(None)` clears the cover.
+/// The cover must already be in the album, otherwise a foreign key violation is returned
+pub async fn update_album(
+    pool: &PgPool,
+    id: i64,
+    owner: &str,
+    name: Option<&str>,
+    cover_hash: Option<Option<&str>>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE albums
+        SET name = COALESCE($3, name),
+            cover_hash = CASE WHEN $4 THEN $5 ELSE cover_hash END,
+            modified_at = $6
+        WHERE id = $1 AND owner = $2
+        "#,
+        id,
+        owner,
+        name,
+        cover_hash.is_some(),
+        cover_hash.flatten(),
+        chrono::Utc::now().naive_utc()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn delete_album(pool: &PgPool, id: i64, owner: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM albums
+        WHERE id = $1 AND owner = $2
+        "#,
+        id,
+        owner
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Images of the album in their custom order
+pub async fn get_album_images(
+    pool: &PgPool,
+    id: i64,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<Image>, sqlx::Error> {
+    let records = sqlx::query_as!(
+        ImageRow,
+        r#"
+        SELECT i.hash, i.extension, i.owner, i.image_name, i.longitude, i.latitude, i.created_at,
+               i.modified_at, i.media_type, i.width, i.height, i.taken_at, i.camera_model,
+               i.description, i.taken_at_override, i.manual_latitude, i.manual_longitude,
+               i.deleted_at, i.favorite, i.rating, i.country, i.region, i.city, i.edited_at
+        FROM album_images ai
+        JOIN images i ON i.hash = ai.hash
+        WHERE ai.album_id = $1 AND i.deleted_at IS NULL
+        ORDER BY ai.position, ai.added_at, ai.hash
+        OFFSET $2
+        LIMIT $3
+        "#,
+        id,
+        offset,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(Image::from).collect())
+}
+
+/// An image of the album that isn't in the trash, whoever added it
+pub async fn get_album_image(
+    pool: &PgPool,
+    id: i64,
+    hash: &str,
+) -> Result<Option<Image>, sqlx::Error> {
+    let record = sqlx::query_as!(
+        ImageRow,
+        r#"
+        SELECT i.hash, i.extension, i.owner, i.image_name, i.longitude, i.latitude, i.created_at,
+               i.modified_at, i.media_type, i.width, i.height, i.taken_at, i.camera_model,
+               i.description, i.taken_at_override, i.manual_latitude, i.manual_longitude,
+               i.deleted_at, i.favorite, i.rating, i.country, i.region, i.city, i.edited_at
+        FROM album_images ai
+        JOIN images i ON i.hash = ai.hash AND i.owner = ai.owner
+        WHERE ai.album_id = $1 AND ai.hash = $2 AND i.deleted_at IS NULL
+        "#,
+        id,
+        hash
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(Image::from))
+}
+
+/// Append images to the album, returns the hashes that were added
+pub async fn add_album_images(
+    pool: &PgPool,
+    id: i64,
+    owner: &str,
+    hashes: &[String],
+) -> Result<Vec<String>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let added = add_album_images_in(&mut tx, id, owner, hashes).await?;
+
+    tx.commit().await?;
+
+    Ok(added)
+}
+
+/// Same as `add_album_images`, inside the caller's transaction. Only the caller's
+/// own images are added, missing, trashed or already present ones are skipped
+pub(super) async fn add_album_images_in(
+    conn: &mut PgConnection,
+    id: i64,
+    owner: &str,
+    hashes: &[String],
+) -> Result<Vec<String>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        INSERT INTO album_images (album_id, owner, hash, position)
+        SELECT $1, $2, i.hash,
+               (SELECT COALESCE(MAX(position), -1) FROM album_images WHERE album_id = $1) + h.ord
+        FROM UNNEST($3::VARCHAR[]) WITH ORDINALITY AS h(hash, ord)
+        JOIN images i ON i.hash = h.hash AND i.owner = $2 AND i.deleted_at IS NULL
+        ON CONFLICT (album_id, hash) DO NOTHING
+        RETURNING hash
+        "#,
+        id,
+        owner,
+        hashes
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    touch_album(conn, id).await?;
+
+    Ok(records.into_iter().map(|r| r.hash).collect())
+}
+
+/// Remove images from the album, returns the hashes that were removed.
+/// With `image_owner` set only that user's images are removed
+pub async fn remove_album_images(
+    pool: &PgPool,
+    id: i64,
+    image_owner: Option<&str>,
+    hashes: &[String],
+) -> Result<Vec<String>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let records = sqlx::query!(
+        r#"
+        DELETE FROM album_images
+        WHERE album_id = $1 AND ($2::VARCHAR IS NULL OR owner = $2) AND hash = ANY($3)
+        RETURNING hash
+        "#,
+        id,
+        image_owner,
+        hashes
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    touch_album(&mut tx, id).await?;
+
+    tx.commit().await?;
+
+    Ok(records.into_iter().map(|r| r.hash).collect())
+}
+
+/// Move `hashes` to the front in the given order, the rest keep their relative order
+pub async fn reorder_album(pool: &PgPool, id: i64, hashes: &[String]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE album_images ai
+        SET position = o.new_position
+        FROM (
+            SELECT m.hash,
+                   (ROW_NUMBER() OVER (ORDER BY h.ord NULLS LAST, m.position, m.added_at, m.hash) - 1)::INTEGER
+                       AS new_position
+            FROM album_images m
+            LEFT JOIN UNNEST($2::VARCHAR[]) WITH ORDINALITY AS h(hash, ord) ON h.hash = m.hash
+            WHERE m.album_id = $1
+        ) o
+        WHERE ai.album_id = $1 AND ai.hash = o.hash
+        "#,
+        id,
+        hashes
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    touch_album(&mut tx, id).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn touch_album(conn: &mut PgConnection, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE albums
+        SET modified_at = $2
+        WHERE id = $1
+        "#,
+        id,
+        chrono::Utc::now().naive_utc()
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_album_members(pool: &PgPool, id: i64) -> Result<Vec<AlbumMember>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT username, role, added_at
+        FROM album_members
+        WHERE album_id = $1
+        ORDER BY added_at, username
+        "#,
+        id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| AlbumMember {
+            username: r.username,
+            role: AlbumRole::parse(&r.role).unwrap_or(AlbumRole::Viewer),
+            added_at: r
+                .added_at
+                .unwrap_or_else(|| chrono::Utc::now().naive_utc())
+                .and_utc(),
+        })
+        .collect())
+}
+
+/// Add a member or change their role, fails with a foreign key violation for unknown users
+pub async fn set_album_member(
+    pool: &PgPool,
+    id: i64,
+    username: &str,
+    role: AlbumRole,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO album_members (album_id, username, role)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (album_id, username) DO UPDATE SET role = EXCLUDED.role
+        "#,
+        id,
+        username,
+        role.as_str()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Remove a member, the images they contributed leave the album with them
+pub async fn remove_album_member(
+    pool: &PgPool,
+    id: i64,
+    username: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM album_members
+        WHERE album_id = $1 AND username = $2
+        "#,
+        id,
+        username
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        DELETE FROM album_images
+        WHERE album_id = $1 AND owner = $2
+        "#,
+        id,
+        username
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    touch_album(&mut tx, id).await?;
+    tx.commit().await?;
+
+    Ok(true)
+}
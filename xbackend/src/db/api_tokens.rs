@@ -0,0 +1,118 @@
+use crate::types::ApiToken;
+use chrono::{NaiveDateTime, Utc};
+use sqlx::PgPool;
+
+struct ApiTokenRow {
+    id: i64,
+    name: String,
+    device_id: i64,
+    created_at: NaiveDateTime,
+    last_used_at: Option<NaiveDateTime>,
+    revoked_at: Option<NaiveDateTime>,
+}
+
+impl From<ApiTokenRow> for ApiToken {
+    fn from(r: ApiTokenRow) -> Self {
+        ApiToken {
+            id: r.id,
+            name: r.name,
+            device_id: r.device_id,
+            created_at: r.created_at.and_utc(),
+            last_used_at: r.last_used_at.map(|t| t.and_utc()),
+            revoked_at: r.revoked_at.map(|t| t.and_utc()),
+        }
+    }
+}
+
+/// Fails with a foreign key violation if the device isn't one of the owner's
+pub async fn create_api_token(
+    pool: &PgPool,
+    owner: &str,
+    device_id: i64,
+    name: &str,
+    token_hash: &str,
+) -> Result<ApiToken, sqlx::Error> {
+    let record = sqlx::query_as!(
+        ApiTokenRow,
+        r#"
+        INSERT INTO api_tokens (owner, device_id, name, token_hash)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, name, device_id, created_at, last_used_at, revoked_at
+        "#,
+        owner,
+        device_id,
+        name,
+        token_hash
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(ApiToken::from(record))
+}
+
+pub async fn get_api_tokens(pool: &PgPool, owner: &str) -> Result<Vec<ApiToken>, sqlx::Error> {
+    let records = sqlx::query_as!(
+        ApiTokenRow,
+        r#"
+        SELECT id, name, device_id, created_at, last_used_at, revoked_at
+        FROM api_tokens
+        WHERE owner = $1
+        ORDER BY created_at DESC, id DESC
+        "#,
+        owner
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(ApiToken::from).collect())
+}
+
+/// Owner and device of a token that isn't revoked, noting that it was used
+pub async fn authenticate_api_token(
+    pool: &PgPool,
+    token_hash: &str,
+) -> Result<Option<(String, i64)>, sqlx::Error> {
+    let now = Utc::now().naive_utc();
+
+    // Only written once a minute, most requests just read
+    let record = sqlx::query!(
+        r#"
+        WITH token AS (
+            SELECT id, owner, device_id
+            FROM api_tokens
+            WHERE token_hash = $1 AND revoked_at IS NULL
+        ),
+        touched AS (
+            UPDATE api_tokens
+            SET last_used_at = $2
+            WHERE id = (SELECT id FROM token)
+              AND (last_used_at IS NULL OR last_used_at < $2::TIMESTAMP - INTERVAL '1 minute')
+        )
+        SELECT owner, device_id
+        FROM token
+        "#,
+        token_hash,
+        now
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(|r| (r.owner, r.device_id)))
+}
+
+pub async fn revoke_api_token(pool: &PgPool, id: i64, owner: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = $3
+        WHERE id = $1 AND owner = $2 AND revoked_at IS NULL
+        "#,
+        id,
+        owner,
+        Utc::now().naive_utc()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,103 @@
+use super::albums::add_album_images_in;
+use super::changes::record_changes;
+use super::images::update_images;
+use crate::types::{BatchOperation, ChangeKind};
+use sqlx::PgPool;
+
+pub struct BatchOutcome {
+    /// Hashes the operation was applied to, others were not found
+    pub affected: Vec<String>,
+    /// `(hash, extension)` of permanently deleted images whose files must go
+    pub deleted_files: Vec<(String, String)>,
+}
+
+/// Apply one operation to a list of the owner's images in a single transaction
+pub async fn apply_batch(
+    pool: &PgPool,
+    owner: &str,
+    hashes: &[String],
+    operation: &BatchOperation,
+) -> Result<BatchOutcome, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let mut deleted_files = Vec::new();
+
+    let affected = match operation {
+        BatchOperation::Delete => {
+            let records = sqlx::query!(
+                r#"
+                DELETE FROM images
+                WHERE hash = ANY($1) AND owner = $2
+                RETURNING hash, extension
+                "#,
+                hashes,
+                owner
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let affected: Vec<String> = records.iter().map(|r| r.hash.clone()).collect();
+            record_changes(&mut tx, owner, &affected, ChangeKind::Deleted).await?;
+
+            deleted_files = records
+                .into_iter()
+                .map(|r| (r.hash, r.extension.unwrap_or_else(|| "jpg".to_string())))
+                .collect();
+
+            affected
+        }
+        BatchOperation::Trash => {
+            let records = sqlx::query!(
+                r#"
+                UPDATE images
+                SET deleted_at = $3
+                WHERE hash = ANY($1) AND owner = $2 AND deleted_at IS NULL
+                RETURNING hash
+                "#,
+                hashes,
+                owner,
+                chrono::Utc::now().naive_utc()
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            // Trashed images disappear from the library, as if deleted
+            let affected: Vec<String> = records.into_iter().map(|r| r.hash).collect();
+            record_changes(&mut tx, owner, &affected, ChangeKind::Deleted).await?;
+            affected
+        }
+        BatchOperation::Restore => {
+            let records = sqlx::query!(
+                r#"
+                UPDATE images
+                SET deleted_at = NULL
+                WHERE hash = ANY($1) AND owner = $2 AND deleted_at IS NOT NULL
+                RETURNING hash
+                "#,
+                hashes,
+                owner
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let affected: Vec<String> = records.into_iter().map(|r| r.hash).collect();
+            record_changes(&mut tx, owner, &affected, ChangeKind::Added).await?;
+            affected
+        }
+        BatchOperation::AddToAlbum(album_id) => {
+            add_album_images_in(&mut tx, *album_id, owner, hashes).await?
+        }
+        BatchOperation::Update(update) => update_images(&mut tx, owner, hashes, update)
+            .await?
+            .into_iter()
+            .map(|image| image.hash)
+            .collect(),
+    };
+
+    tx.commit().await?;
+
+    Ok(BatchOutcome {
+        affected,
+        deleted_files,
+    })
+}
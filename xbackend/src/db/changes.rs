@@ -0,0 +1,150 @@
+use crate::types::{ChangeKind, ImageChange, LibraryEvent};
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool, postgres::PgListener};
+use tokio::sync::broadcast;
+
+/// Payload sent by the `image_changes_notify` trigger
+#[derive(Deserialize)]
+struct ChangeNotification {
+    owner: String,
+    #[serde(flatten)]
+    change: ImageChange,
+}
+
+/// Append an entry to the owner's change journal
+pub async fn record_change(
+    conn: &mut PgConnection,
+    owner: &str,
+    hash: &str,
+    kind: ChangeKind,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO image_changes (owner, hash, kind)
+        VALUES ($1, $2, $3)
+        "#,
+        owner,
+        hash,
+        kind.as_str()
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Append one journal entry per hash
+pub async fn record_changes(
+    conn: &mut PgConnection,
+    owner: &str,
+    hashes: &[String],
+    kind: ChangeKind,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO image_changes (owner, hash, kind)
+        SELECT $1, hash, $3
+        FROM UNNEST($2::VARCHAR[]) AS hash
+        "#,
+        owner,
+        hashes,
+        kind.as_str()
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Get the owner's journal entries after `since`, in commit order.
+/// Entries of transactions still running, and of every later one, are held
+/// back until it ends. Pages end on a whole transaction so they can run past `limit`
+pub async fn get_changes_since(
+    pool: &PgPool,
+    owner: &str,
+    since: i64,
+    limit: i64,
+) -> Result<Vec<ImageChange>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        WITH page AS (
+            SELECT xact_id
+            FROM image_changes
+            WHERE owner = $1 AND xact_id > $2
+              AND xact_id < pg_snapshot_xmin(pg_current_snapshot())::TEXT::BIGINT
+            ORDER BY xact_id, id
+            LIMIT $3
+        )
+        SELECT xact_id, hash, kind, changed_at
+        FROM image_changes
+        WHERE owner = $1 AND xact_id > $2 AND xact_id <= (SELECT MAX(xact_id) FROM page)
+        ORDER BY xact_id, id
+        "#,
+        owner,
+        since,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| ImageChange {
+            cursor: r.xact_id,
+            hash: r.hash,
+            // Unknown kinds make the client re-fetch the image
+            kind: ChangeKind::parse(&r.kind).unwrap_or(ChangeKind::Updated),
+            changed_at: chrono::DateTime::from_naive_utc_and_offset(
+                r.changed_at
+                    .unwrap_or_else(|| chrono::Utc::now().naive_utc()),
+                chrono::Utc,
+            ),
+        })
+        .collect())
+}
+
+/// Forward committed journal entries to `events` until the process exits
+pub async fn listen_changes(pool: PgPool, events: broadcast::Sender<LibraryEvent>) {
+    loop {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+
+        if let Err(e) = listener.listen("image_changes").await {
+            eprintln!("Database error: {:?}", e);
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            continue;
+        }
+
+        loop {
+            match listener.try_recv().await {
+                Ok(Some(notification)) => {
+                    match serde_json::from_str::<ChangeNotification>(notification.payload()) {
+                        Ok(n) => {
+                            // Sending only fails when nobody is subscribed
+                            let _ = events.send(LibraryEvent::Changed {
+                                owner: n.owner,
+                                change: n.change,
+                            });
+                        }
+                        Err(e) => eprintln!("Invalid change notification: {:?}", e),
+                    }
+                }
+                // The connection was lost and re-established, notifications may be gone
+                Ok(None) => {
+                    let _ = events.send(LibraryEvent::Resync);
+                }
+                Err(e) => {
+                    eprintln!("Database error: {:?}", e);
+                    let _ = events.send(LibraryEvent::Resync);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use crate::types::{Device, DeviceFile, ImageSource, SyncStatus};
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+
+struct DeviceRow {
+    id: i64,
+    name: String,
+    platform: Option<String>,
+    created_at: Option<NaiveDateTime>,
+    last_seen_at: Option<NaiveDateTime>,
+    last_sync_at: Option<NaiveDateTime>,
+    last_sync_status: Option<String>,
+    last_sync_cursor: Option<i64>,
+    last_success_at: Option<NaiveDateTime>,
+}
+
+impl From<DeviceRow> for Device {
+    fn from(r: DeviceRow) -> Self {
+        Device {
+            id: r.id,
+            name: r.name,
+            platform: r.platform,
+            created_at: r
+                .created_at
+                .unwrap_or_else(|| chrono::Utc::now().naive_utc())
+                .and_utc(),
+            last_seen_at: r.last_seen_at.map(|t| t.and_utc()),
+            last_sync_at: r.last_sync_at.map(|t| t.and_utc()),
+            last_sync_status: r.last_sync_status.as_deref().and_then(SyncStatus::parse),
+            last_sync_cursor: r.last_sync_cursor,
+            last_success_at: r.last_success_at.map(|t| t.and_utc()),
+        }
+    }
+}
+
+pub async fn create_device(
+    pool: &PgPool,
+    owner: &str,
+    name: &str,
+    platform: Option<&str>,
+) -> Result<Device, sqlx::Error> {
+    let r = sqlx::query_as!(
+        DeviceRow,
+        r#"
+        INSERT INTO devices (owner, name, platform)
+        VALUES ($1, $2, $3)
+        RETURNING id, name, platform, created_at, last_seen_at, last_sync_at, last_sync_status,
+                  last_sync_cursor, last_success_at
+        "#,
+        owner,
+        name,
+        platform
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Device::from(r))
+}
+
+pub async fn get_devices(pool: &PgPool, owner: &str) -> Result<Vec<Device>, sqlx::Error> {
+    let records = sqlx::query_as!(
+        DeviceRow,
+        r#"
+        SELECT id, name, platform, created_at, last_seen_at, last_sync_at, last_sync_status,
+               last_sync_cursor, last_success_at
+        FROM devices
+        WHERE owner = $1
+        ORDER BY created_at
+        "#,
+        owner
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(Device::from).collect())
+}
+
+pub async fn get_device(
+    pool: &PgPool,
+    id: i64,
+    owner: &str,
+) -> Result<Option<Device>, sqlx::Error> {
+    let record = sqlx::query_as!(
+        DeviceRow,
+        r#"
+        SELECT id, name, platform, created_at, last_seen_at, last_sync_at, last_sync_status,
+               last_sync_cursor, last_success_at
+        FROM devices
+        WHERE id = $1 AND owner = $2
+        "#,
+        id,
+        owner
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(Device::from))
+}
+
+/// Delete a device, the provenance of its uploads goes with it
+pub async fn delete_device(pool: &PgPool, id: i64, owner: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM devices
+        WHERE id = $1 AND owner = $2
+        "#,
+        id,
+        owner
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record the outcome of a sync run reported by the device
+pub async fn record_sync(
+    pool: &PgPool,
+    id: i64,
+    owner: &str,
+    status: SyncStatus,
+    cursor: Option<i64>,
+) -> Result<Option<Device>, sqlx::Error> {
+    let now = chrono::Utc::now().naive_utc();
+
+    let record = sqlx::query_as!(
+        DeviceRow,
+        r#"
+        UPDATE devices
+        SET last_sync_at = $3,
+            last_seen_at = $3,
+            last_sync_status = $4,
+            last_sync_cursor = COALESCE($5, last_sync_cursor),
+            last_success_at = CASE WHEN $4::VARCHAR = 'succeeded' THEN $3 ELSE last_success_at END
+        WHERE id = $1 AND owner = $2
+        RETURNING id, name, platform, created_at, last_seen_at, last_sync_at, last_sync_status,
+                  last_sync_cursor, last_success_at
+        "#,
+        id,
+        owner,
+        now,
+        status.as_str(),
+        cursor
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(Device::from))
+}
+
+/// Remember where an uploaded image lives on the device, a path can only hold one file
+pub async fn record_source(
+    pool: &PgPool,
+    owner: &str,
+    hash: &str,
+    source: &ImageSource,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let now = chrono::Utc::now().naive_utc();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO image_sources (device_id, relative_path, owner, hash, source_folder, uploaded_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (device_id, relative_path)
+        DO UPDATE SET hash = EXCLUDED.hash,
+                      source_folder = EXCLUDED.source_folder,
+                      uploaded_at = EXCLUDED.uploaded_at
+        "#,
+        source.device_id,
+        source.relative_path,
+        owner,
+        hash,
+        source.source_folder,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE devices
+        SET last_seen_at = $3
+        WHERE id = $1 AND owner = $2
+        "#,
+        source.device_id,
+        owner,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Files uploaded from a device ordered by path, continuing after `after`
+pub async fn get_device_files(
+    pool: &PgPool,
+    id: i64,
+    owner: &str,
+    after: Option<&str>,
+    limit: i64,
+) -> Result<Vec<DeviceFile>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT s.hash, s.relative_path, s.source_folder, s.uploaded_at
+        FROM image_sources s
+        JOIN images i ON i.hash = s.hash
+        WHERE s.device_id = $1 AND s.owner = $2 AND i.deleted_at IS NULL
+          AND ($3::VARCHAR IS NULL OR s.relative_path > $3)
+        ORDER BY s.relative_path
+        LIMIT $4
+        "#,
+        id,
+        owner,
+        after,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| DeviceFile {
+            hash: r.hash,
+            relative_path: r.relative_path,
+            source_folder: r.source_folder,
+            uploaded_at: r
+                .uploaded_at
+                .unwrap_or_else(|| chrono::Utc::now().naive_utc())
+                .and_utc(),
+        })
+        .collect())
+}
@@ -0,0 +1,435 @@
+use super::images::ImageRow;
+use crate::types::{Event, Image};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+
+struct EventRow {
+    id: i64,
+    title: String,
+    place: Option<String>,
+    starts_at: NaiveDateTime,
+    ends_at: NaiveDateTime,
+    image_count: i64,
+    cover_hash: Option<String>,
+}
+
+impl From<EventRow> for Event {
+    fn from(r: EventRow) -> Self {
+        Event {
+            id: r.id,
+            title: r.title,
+            place: r.place,
+            starts_at: r.starts_at.and_utc(),
+            ends_at: r.ends_at.and_utc(),
+            image_count: r.image_count,
+            cover_hash: r.cover_hash,
+        }
+    }
+}
+
+/// Image as seen by the clustering
+pub struct EventImage {
+    pub hash: String,
+    pub captured_at: DateTime<Utc>,
+    pub local_time: NaiveDateTime, // capture time on the camera's clock
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+}
+
+/// Event computed by the worker, `hashes` in capture order
+pub struct NewEvent {
+    pub id: Option<i64>, // the existing event it carries on, keeping its id
+    pub title: String,
+    pub place: Option<String>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub hashes: Vec<String>,
+}
+
+/// Users with committed journal entries the event worker hasn't seen, and the
+/// cursor of the latest one. Held back like the sync journal, see `get_changes_since`
+pub async fn get_event_backlog(pool: &PgPool) -> Result<Vec<(String, i64)>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT c.owner, MAX(c.xact_id) AS "last_cursor!"
+        FROM image_changes c
+        LEFT JOIN event_cursors ec ON ec.owner = c.owner
+        WHERE c.xact_id > COALESCE(ec.last_cursor, 0)
+          AND c.xact_id < pg_snapshot_xmin(pg_current_snapshot())::TEXT::BIGINT
+        GROUP BY c.owner
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| (r.owner, r.last_cursor))
+        .collect())
+}
+
+/// Time span touched by the journal entries after the user's cursor, up to
+/// `last_cursor`: where the changed images are now and the events they were in
+pub async fn get_changed_span(
+    pool: &PgPool,
+    owner: &str,
+    last_cursor: i64,
+) -> Result<Option<(DateTime<Utc>, DateTime<Utc>)>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        WITH changed AS (
+            SELECT DISTINCT c.hash
+            FROM image_changes c
+            LEFT JOIN event_cursors ec ON ec.owner = c.owner
+            WHERE c.owner = $1 AND c.xact_id > COALESCE(ec.last_cursor, 0) AND c.xact_id <= $2
+        ),
+        times AS (
+            SELECT i.captured_at AS t
+            FROM images i
+            JOIN changed ON changed.hash = i.hash
+            WHERE i.owner = $1
+            UNION ALL
+            SELECT UNNEST(ARRAY[e.starts_at, e.ends_at])
+            FROM events e
+            JOIN event_images ei ON ei.event_id = e.id
+            JOIN changed ON changed.hash = ei.hash
+            WHERE e.owner = $1
+        )
+        SELECT MIN(t) AS first, MAX(t) AS last
+        FROM times
+        "#,
+        owner,
+        last_cursor
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(record
+        .first
+        .zip(record.last)
+        .map(|(first, last)| (first.and_utc(), last.and_utc())))
+}
+
+/// Widen `first..=last` to the nearest pauses longer than `gap` in the user's
+/// library, where an event can't continue
+pub async fn get_gap_span(
+    pool: &PgPool,
+    owner: &str,
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+    gap: Duration,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        WITH ordered AS (
+            SELECT captured_at,
+                   captured_at - LAG(captured_at) OVER w > $4 AS gap_before,
+                   LEAD(captured_at) OVER w - captured_at > $4 AS gap_after
+            FROM images
+            WHERE owner = $1 AND deleted_at IS NULL
+            WINDOW w AS (ORDER BY captured_at, hash)
+        )
+        SELECT (SELECT MAX(captured_at)
+                FROM ordered
+                WHERE captured_at <= $2 AND gap_before IS NOT FALSE) AS first,
+               (SELECT MIN(captured_at)
+                FROM ordered
+                WHERE captured_at >= $3 AND gap_after IS NOT FALSE) AS last
+        "#,
+        owner,
+        first.naive_utc(),
+        last.naive_utc(),
+        gap as _
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((
+        record.first.map_or(first, |t| t.and_utc()),
+        record.last.map_or(last, |t| t.and_utc()),
+    ))
+}
+
+/// Span of the user's events overlapping `from..=to`, widened to contain it
+pub async fn get_event_span(
+    pool: &PgPool,
+    owner: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT LEAST(MIN(starts_at), $2) AS "first!", GREATEST(MAX(ends_at), $3) AS "last!"
+        FROM events
+        WHERE owner = $1 AND ends_at >= $2 AND starts_at <= $3
+        "#,
+        owner,
+        from.naive_utc(),
+        to.naive_utc()
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((record.first.and_utc(), record.last.and_utc()))
+}
+
+/// The user's images in the library captured within `from..=to`, oldest first
+pub async fn get_event_candidates(
+    pool: &PgPool,
+    owner: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<EventImage>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT hash, captured_at AS "captured_at!",
+               captured_at + COALESCE(taken_at_offset, 0) * INTERVAL '1 minute' AS "local_time!",
+               effective_latitude AS latitude, effective_longitude AS longitude,
+               country, region, city
+        FROM images
+        WHERE owner = $1 AND deleted_at IS NULL AND captured_at BETWEEN $2 AND $3
+        ORDER BY captured_at, hash
+        "#,
+        owner,
+        from.naive_utc(),
+        to.naive_utc()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| EventImage {
+            hash: r.hash,
+            captured_at: r.captured_at.and_utc(),
+            local_time: r.local_time,
+            latitude: r.latitude,
+            longitude: r.longitude,
+            country: r.country,
+            region: r.region,
+            city: r.city,
+        })
+        .collect())
+}
+
+/// The user's events within `from..=to` and their images, the ones
+/// `replace_events` swaps out
+pub async fn get_replaced_events(
+    pool: &PgPool,
+    owner: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<(i64, Vec<String>)>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT e.id, ARRAY_REMOVE(ARRAY_AGG(ei.hash), NULL) AS "hashes!"
+        FROM events e
+        LEFT JOIN event_images ei ON ei.event_id = e.id
+        WHERE e.owner = $1 AND e.starts_at >= $2 AND e.ends_at <= $3
+        GROUP BY e.id
+        ORDER BY e.id
+        "#,
+        owner,
+        from.naive_utc(),
+        to.naive_utc()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(|r| (r.id, r.hashes)).collect())
+}
+
+/// Swap the user's events within `from..=to` for the recomputed ones and
+/// move the cursor to `last_cursor`. Events with an id are updated in place,
+/// the others in the span are deleted
+pub async fn replace_events(
+    pool: &PgPool,
+    owner: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    events: &[NewEvent],
+    last_cursor: i64,
+) -> Result<(), sqlx::Error> {
+    let kept: Vec<i64> = events.iter().filter_map(|event| event.id).collect();
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM events
+        WHERE owner = $1 AND starts_at >= $2 AND ends_at <= $3 AND id <> ALL($4)
+        "#,
+        owner,
+        from.naive_utc(),
+        to.naive_utc(),
+        &kept
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for event in events {
+        let Some(id) = event.id else {
+            sqlx::query!(
+                r#"
+                WITH event AS (
+                    INSERT INTO events (owner, title, place, starts_at, ends_at, cover_hash)
+                    VALUES ($1, $2, $3, $4, $5, ($6::VARCHAR[])[1])
+                    RETURNING id
+                )
+                INSERT INTO event_images (event_id, hash)
+                SELECT event.id, hash
+                FROM event, UNNEST($6::VARCHAR[]) AS hash
+                "#,
+                owner,
+                event.title,
+                event.place,
+                event.starts_at.naive_utc(),
+                event.ends_at.naive_utc(),
+                &event.hashes
+            )
+            .execute(&mut *tx)
+            .await?;
+            continue;
+        };
+
+        sqlx::query!(
+            r#"
+            UPDATE events
+            SET title = $3, place = $4, starts_at = $5, ends_at = $6,
+                cover_hash = ($7::VARCHAR[])[1], computed_at = $8
+            WHERE id = $1 AND owner = $2
+            "#,
+            id,
+            owner,
+            event.title,
+            event.place,
+            event.starts_at.naive_utc(),
+            event.ends_at.naive_utc(),
+            &event.hashes,
+            Utc::now().naive_utc()
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            WITH removed AS (
+                DELETE FROM event_images
+                WHERE event_id = $1 AND hash <> ALL($2)
+            )
+            INSERT INTO event_images (event_id, hash)
+            SELECT $1, hash
+            FROM UNNEST($2::VARCHAR[]) AS hash
+            ON CONFLICT DO NOTHING
+            "#,
+            id,
+            &event.hashes
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    set_event_cursor(&mut tx, owner, last_cursor).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Mark the journal as processed up to `last_cursor`
+pub async fn set_event_cursor(
+    conn: &mut PgConnection,
+    owner: &str,
+    last_cursor: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO event_cursors (owner, last_cursor)
+        VALUES ($1, $2)
+        ON CONFLICT (owner) DO UPDATE SET last_cursor = EXCLUDED.last_cursor
+        "#,
+        owner,
+        last_cursor
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// The user's events, most recent first
+pub async fn get_events(pool: &PgPool, owner: &str) -> Result<Vec<Event>, sqlx::Error> {
+    let records = sqlx::query_as!(
+        EventRow,
+        r#"
+        SELECT e.id, e.title, e.place, e.starts_at, e.ends_at, e.cover_hash,
+               (SELECT COUNT(*)
+                FROM event_images ei
+                JOIN images i ON i.hash = ei.hash
+                WHERE ei.event_id = e.id AND i.deleted_at IS NULL) AS "image_count!"
+        FROM events e
+        WHERE e.owner = $1
+        ORDER BY e.starts_at DESC
+        "#,
+        owner
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(Event::from).collect())
+}
+
+pub async fn get_event(pool: &PgPool, id: i64, owner: &str) -> Result<Option<Event>, sqlx::Error> {
+    let record = sqlx::query_as!(
+        EventRow,
+        r#"
+        SELECT e.id, e.title, e.place, e.starts_at, e.ends_at, e.cover_hash,
+               (SELECT COUNT(*)
+                FROM event_images ei
+                JOIN images i ON i.hash = ei.hash
+                WHERE ei.event_id = e.id AND i.deleted_at IS NULL) AS "image_count!"
+        FROM events e
+        WHERE e.id = $1 AND e.owner = $2
+        "#,
+        id,
+        owner
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(Event::from))
+}
+
+/// Images of the event in capture order
+pub async fn get_event_images(
+    pool: &PgPool,
+    id: i64,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<Image>, sqlx::Error> {
+    let records = sqlx::query_as!(
+        ImageRow,
+        r#"
+        SELECT i.hash, i.extension, i.owner, i.image_name, i.longitude, i.latitude, i.created_at,
+               i.modified_at, i.media_type, i.width, i.height, i.taken_at, i.camera_model,
+               i.description, i.taken_at_override, i.manual_latitude, i.manual_longitude,
+               i.deleted_at, i.favorite, i.rating, i.country, i.region, i.city, i.edited_at
+        FROM event_images ei
+        JOIN images i ON i.hash = ei.hash
+        WHERE ei.event_id = $1 AND i.deleted_at IS NULL
+        ORDER BY i.captured_at, i.hash
+        OFFSET $2
+        LIMIT $3
+        "#,
+        id,
+        offset,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(Image::from).collect())
+}
@@ -0,0 +1,93 @@
+use super::images::{IMAGE_COLUMNS, ImageRow, Visibility, push_filter, push_visible};
+use crate::types::{Image, ImageFilter, Location, MapCluster};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+
+#[derive(FromRow)]
+struct NearbyImageRow {
+    #[sqlx(flatten)]
+    image: ImageRow,
+    distance: f64,
+}
+
+/// The images visible to the user within `radius_km` of `center`, closest first,
+/// with their distance in km
+pub async fn get_images_near(
+    pool: &PgPool,
+    username: &str,
+    center: Location,
+    radius_km: f64,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<(Image, f64)>, sqlx::Error> {
+    let visibility = Visibility::load(pool, username, false).await?;
+    let radius = radius_km * 1000.0;
+
+    let mut query = QueryBuilder::<Postgres>::new("SELECT ");
+    query.push(IMAGE_COLUMNS);
+    query.push(", distance FROM (SELECT *, earth_distance(ll_to_earth(");
+    query.push_bind(center.latitude);
+    query.push(", ");
+    query.push_bind(center.longitude);
+    query.push("), ll_to_earth(effective_latitude, effective_longitude)) AS distance FROM ");
+    // earth_box is a cheap indexed pre-filter, the exact distance check follows
+    push_visible(&mut query, &visibility, |query| {
+        query.push(
+            " AND deleted_at IS NULL AND effective_latitude IS NOT NULL \
+             AND earth_box(ll_to_earth(",
+        );
+        query.push_bind(center.latitude);
+        query.push(", ");
+        query.push_bind(center.longitude);
+        query.push("), ");
+        query.push_bind(radius);
+        query.push(") @> ll_to_earth(effective_latitude, effective_longitude)");
+    });
+    query.push(") nearby WHERE distance <= ");
+    query.push_bind(radius);
+    query.push(" ORDER BY distance, hash OFFSET ");
+    query.push_bind(offset);
+    query.push(" LIMIT ");
+    query.push_bind(limit);
+
+    let records = query
+        .build_query_as::<NearbyImageRow>()
+        .fetch_all(pool)
+        .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| (Image::from(r.image), r.distance / 1000.0))
+        .collect())
+}
+
+/// Group the matching geotagged images visible to the user into square cells
+/// of `cell_size` degrees
+pub async fn get_map_clusters(
+    pool: &PgPool,
+    username: &str,
+    filter: &ImageFilter,
+    cell_size: f64,
+) -> Result<Vec<MapCluster>, sqlx::Error> {
+    let visibility = Visibility::load(pool, username, filter.owner.is_some()).await?;
+
+    let mut query = QueryBuilder::<Postgres>::new(
+        r#"
+        SELECT AVG(effective_latitude) AS latitude,
+               AVG(effective_longitude) AS longitude,
+               COUNT(*) AS count,
+               (ARRAY_AGG(hash ORDER BY captured_at DESC, hash DESC))[1] AS hash
+        FROM "#,
+    );
+    push_visible(&mut query, &visibility, |query| {
+        query.push(" AND effective_latitude IS NOT NULL");
+        push_filter(query, filter);
+    });
+
+    query.push(" GROUP BY FLOOR(effective_latitude / ");
+    query.push_bind(cell_size);
+    query.push("), FLOOR(effective_longitude / ");
+    query.push_bind(cell_size);
+    query.push(") ORDER BY count DESC");
+
+    query.build_query_as::<MapCluster>().fetch_all(pool).await
+}
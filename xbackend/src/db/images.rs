@@ -1,11 +1,129 @@
-use crate::types::Image;
-use sqlx::PgPool;
+use super::changes::{record_change, record_changes};
+use crate::types::{
+    ChangeKind, CursorKey, Image, ImageCursor, ImageFilter, ImageSort, ImageUpdate, MediaType,
+    Place,
+};
+use chrono::NaiveDateTime;
+use sqlx::{FromRow, PgConnection, PgPool, Postgres, QueryBuilder};
 
-pub async fn insert_image(pool: &PgPool, image: &Image) -> Result<(), sqlx::Error> {
+pub(super) const IMAGE_COLUMNS: &str = "hash, extension, owner, image_name, longitude, latitude, \
+     created_at, modified_at, media_type, width, height, taken_at, camera_model, description, \
+     taken_at_override, manual_latitude, manual_longitude, deleted_at, favorite, rating, \
+     country, region, city, edited_at";
+
+/// Row shape of `images`, shared by every query returning full records
+#[derive(FromRow)]
+pub(super) struct ImageRow {
+    pub(super) hash: String,
+    pub(super) extension: Option<String>,
+    pub(super) owner: Option<String>,
+    pub(super) image_name: Option<String>,
+    pub(super) longitude: Option<f64>,
+    pub(super) latitude: Option<f64>,
+    pub(super) created_at: Option<NaiveDateTime>,
+    pub(super) modified_at: Option<NaiveDateTime>,
+    pub(super) media_type: String,
+    pub(super) width: Option<i32>,
+    pub(super) height: Option<i32>,
+    pub(super) taken_at: Option<NaiveDateTime>,
+    pub(super) camera_model: Option<String>,
+    pub(super) description: Option<String>,
+    pub(super) taken_at_override: Option<NaiveDateTime>,
+    pub(super) manual_latitude: Option<f64>,
+    pub(super) manual_longitude: Option<f64>,
+    pub(super) deleted_at: Option<NaiveDateTime>,
+    pub(super) favorite: bool,
+    pub(super) rating: Option<i16>,
+    pub(super) country: Option<String>,
+    pub(super) region: Option<String>,
+    pub(super) city: Option<String>,
+    pub(super) edited_at: Option<NaiveDateTime>,
+}
+
+impl From<ImageRow> for Image {
+    fn from(r: ImageRow) -> Self {
+        Image {
+            hash: r.hash,
+            extension: r.extension.unwrap_or_else(|| "jpg".to_string()),
+            owner: r.owner.unwrap_or_default(),
+            image_name: r.image_name,
+            longitude: r.longitude,
+            latitude: r.latitude,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(
+                r.created_at
+                    .unwrap_or_else(|| chrono::Utc::now().naive_utc()),
+                chrono::Utc,
+            ),
+            modified_at: chrono::DateTime::from_naive_utc_and_offset(
+                r.modified_at
+                    .unwrap_or_else(|| chrono::Utc::now().naive_utc()),
+                chrono::Utc,
+            ),
+            media_type: MediaType::parse(&r.media_type).unwrap_or(MediaType::Other),
+            width: r.width,
+            height: r.height,
+            taken_at: r
+                .taken_at
+                .map(|t| chrono::DateTime::from_naive_utc_and_offset(t, chrono::Utc)),
+            camera_model: r.camera_model,
+            description: r.description,
+            taken_at_override: r
+                .taken_at_override
+                .map(|t| chrono::DateTime::from_naive_utc_and_offset(t, chrono::Utc)),
+            manual_latitude: r.manual_latitude,
+            manual_longitude: r.manual_longitude,
+            deleted_at: r
+                .deleted_at
+                .map(|t| chrono::DateTime::from_naive_utc_and_offset(t, chrono::Utc)),
+            favorite: r.favorite,
+            rating: r.rating,
+            place: match (r.country, r.city) {
+                (Some(country), Some(city)) => Some(Place {
+                    country,
+                    region: r.region,
+                    city,
+                }),
+                _ => None,
+            },
+            edited_at: r.edited_at.map(|t| t.and_utc()),
+        }
+    }
+}
+
+/// Record a new image, returns false without inserting it when it would take
+/// the owner over their quota
+pub async fn insert_image(
+    pool: &PgPool,
+    image: &Image,
+    taken_at_offset: Option<i16>,
+    size_bytes: i64,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // Locking the user makes concurrent uploads take turns, so together they
+    // can't go past the quota. The usage is read after the lock is held
     sqlx::query!(
         r#"
-        INSERT INTO images (hash, extension, owner, image_name, longitude, latitude, created_at, modified_at)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        SELECT 1 AS locked
+        FROM users
+        WHERE username = $1
+        FOR UPDATE
+        "#,
+        image.owner
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if quota_exceeded(&mut tx, &image.owner, &image.hash, size_bytes).await? {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO images (hash, extension, owner, image_name, longitude, latitude, created_at, modified_at,
+                            media_type, width, height, taken_at, camera_model, rating, size_bytes,
+                            taken_at_offset)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
         "#,
         image.hash,
         image.extension,
@@ -14,7 +132,100 @@ pub async fn insert_image(pool: &PgPool, image: &Image) -> Result<(), sqlx::Erro
         image.longitude,
         image.latitude,
         image.created_at.naive_utc(),
-        image.modified_at.naive_utc()
+        image.modified_at.naive_utc(),
+        image.media_type.as_str(),
+        image.width,
+        image.height,
+        image.taken_at.map(|t| t.naive_utc()),
+        image.camera_model,
+        image.rating,
+        size_bytes,
+        taken_at_offset
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    record_change(&mut tx, &image.owner, &image.hash, ChangeKind::Added).await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// Whether storing a new file of `size_bytes` would take the owner over their
+/// quota, files that are already stored don't count. Only spares writing the
+/// file, `insert_image` is what enforces the quota
+pub async fn exceeds_quota(
+    pool: &PgPool,
+    owner: &str,
+    hash: &str,
+    size_bytes: i64,
+) -> Result<bool, sqlx::Error> {
+    quota_exceeded(&mut *pool.acquire().await?, owner, hash, size_bytes).await
+}
+
+/// The quota check shared by `exceeds_quota` and `insert_image`
+async fn quota_exceeded(
+    conn: &mut PgConnection,
+    owner: &str,
+    hash: &str,
+    size_bytes: i64,
+) -> Result<bool, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM users u
+            WHERE u.username = $1
+              AND u.quota_bytes IS NOT NULL
+              AND NOT EXISTS (SELECT 1 FROM images WHERE hash = $2)
+              AND (SELECT COALESCE(SUM(size_bytes), 0)::BIGINT FROM images WHERE owner = $1)
+                  + $3::BIGINT
+                  > u.quota_bytes
+        ) AS "exceeds!"
+        "#,
+        owner,
+        hash,
+        size_bytes
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(record.exceeds)
+}
+
+/// Images stored before sizes were recorded, as `(hash, extension)`
+pub async fn get_unsized_images(
+    pool: &PgPool,
+    limit: i64,
+) -> Result<Vec<(String, String)>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT hash, extension
+        FROM images
+        WHERE size_bytes IS NULL
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| (r.hash, r.extension.unwrap_or_else(|| "jpg".to_string())))
+        .collect())
+}
+
+pub async fn set_image_size(pool: &PgPool, hash: &str, size_bytes: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE images
+        SET size_bytes = $2
+        WHERE hash = $1
+        "#,
+        hash,
+        size_bytes
     )
     .execute(pool)
     .await?;
@@ -30,7 +241,7 @@ pub async fn get_image_hashes_by_owner(
         r#"
         SELECT hash
         FROM images
-        WHERE owner = $1
+        WHERE owner = $1 AND deleted_at IS NULL
         ORDER BY created_at DESC
         "#,
         owner
@@ -41,44 +252,411 @@ pub async fn get_image_hashes_by_owner(
     Ok(records.into_iter().map(|r| r.hash).collect())
 }
 
+/// Get the subset of `hashes` that is already stored, flagged with whether
+/// the owner is the one storing it. Hashes are unique across users
+pub async fn get_existing_hashes(
+    pool: &PgPool,
+    owner: &str,
+    hashes: &[String],
+) -> Result<Vec<(String, bool)>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT hash, owner IS NOT DISTINCT FROM $1 AS "owned!"
+        FROM images
+        WHERE hash = ANY($2)
+        "#,
+        owner,
+        hashes
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(|r| (r.hash, r.owned)).collect())
+}
+
 pub async fn get_image_by_hash(
     pool: &PgPool,
     hash: &str,
-    owner: &str,
+    username: &str,
 ) -> Result<Option<Image>, sqlx::Error> {
-    let record = sqlx::query!(
+    let record = sqlx::query_as!(
+        ImageRow,
         r#"
-        SELECT hash, extension, owner, image_name, longitude, latitude, created_at, modified_at
+        SELECT hash, extension, owner, image_name, longitude, latitude, created_at, modified_at,
+               media_type, width, height, taken_at, camera_model, description, taken_at_override,
+               manual_latitude, manual_longitude, deleted_at, favorite, rating, country, region,
+               city, edited_at
         FROM images
-        WHERE hash = $1 AND owner = $2
+        WHERE hash = $1
+          AND (owner = $2 OR (deleted_at IS NULL AND (
+              EXISTS (
+                  SELECT 1 FROM album_images ai
+                  WHERE ai.hash = images.hash
+                    AND ai.album_id IN (SELECT id FROM albums WHERE owner = $2
+                                        UNION ALL
+                                        SELECT album_id FROM album_members WHERE username = $2))
+              OR EXISTS (
+                  SELECT 1 FROM partner_shares p
+                  WHERE p.owner = images.owner AND p.partner = $2
+                    AND (p.since IS NULL OR images.captured_at >= p.since)))))
         "#,
         hash,
-        owner
+        username
     )
     .fetch_optional(pool)
     .await?;
 
-    Ok(record.map(|r| Image {
-        hash: r.hash,
-        extension: r.extension.unwrap_or_else(|| "jpg".to_string()),
-        owner: r.owner.unwrap_or_default(),
-        image_name: r.image_name,
-        longitude: r.longitude,
-        latitude: r.latitude,
-        created_at: chrono::DateTime::from_naive_utc_and_offset(
-            r.created_at
-                .unwrap_or_else(|| chrono::Utc::now().naive_utc()),
-            chrono::Utc,
-        ),
-        modified_at: chrono::DateTime::from_naive_utc_and_offset(
-            r.modified_at
-                .unwrap_or_else(|| chrono::Utc::now().naive_utc()),
-            chrono::Utc,
-        ),
-    }))
+    Ok(record.map(Image::from))
+}
+
+/// Apply a metadata edit, returns the updated image or `None` if it doesn't
+/// exist or is in the trash
+pub async fn update_image(
+    pool: &PgPool,
+    hash: &str,
+    owner: &str,
+    update: &ImageUpdate,
+) -> Result<Option<Image>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let mut images = update_images(&mut tx, owner, &[hash.to_string()], update).await?;
+
+    tx.commit().await?;
+
+    Ok(images.pop())
+}
+
+/// Apply the same metadata edit to several images and journal it, images in
+/// the trash are left alone
+pub(super) async fn update_images(
+    conn: &mut PgConnection,
+    owner: &str,
+    hashes: &[String],
+    update: &ImageUpdate,
+) -> Result<Vec<Image>, sqlx::Error> {
+    let location = update.location.flatten();
+
+    let records = sqlx::query_as!(
+        ImageRow,
+        r#"
+        UPDATE images
+        SET image_name = COALESCE($3, image_name),
+            description = CASE WHEN $4 THEN $5 ELSE description END,
+            taken_at_override = CASE WHEN $6 THEN $7 ELSE taken_at_override END,
+            manual_latitude = CASE WHEN $8 THEN $9 ELSE manual_latitude END,
+            manual_longitude = CASE WHEN $8 THEN $10 ELSE manual_longitude END,
+            -- A new location has to go through the geocoder again
+            country = CASE WHEN $8 THEN NULL ELSE country END,
+            region = CASE WHEN $8 THEN NULL ELSE region END,
+            city = CASE WHEN $8 THEN NULL ELSE city END,
+            geocoded_at = CASE WHEN $8 THEN NULL ELSE geocoded_at END,
+            favorite = COALESCE($11, favorite),
+            rating = CASE WHEN $12 THEN $13 ELSE rating END,
+            edited_at = $14
+        WHERE hash = ANY($1) AND owner = $2 AND deleted_at IS NULL
+        RETURNING hash, extension, owner, image_name, longitude, latitude, created_at, modified_at,
+                  media_type, width, height, taken_at, camera_model, description, taken_at_override,
+                  manual_latitude, manual_longitude, deleted_at, favorite, rating, country,
+                  region, city, edited_at
+        "#,
+        hashes,
+        owner,
+        update.image_name,
+        update.description.is_some(),
+        update.description.clone().flatten(),
+        update.taken_at.is_some(),
+        update.taken_at.flatten().map(|t| t.naive_utc()),
+        update.location.is_some(),
+        location.map(|l| l.latitude),
+        location.map(|l| l.longitude),
+        update.favorite,
+        update.rating.is_some(),
+        update.rating.flatten(),
+        chrono::Utc::now().naive_utc()
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let updated: Vec<String> = records.iter().map(|r| r.hash.clone()).collect();
+    record_changes(conn, owner, &updated, ChangeKind::Updated).await?;
+
+    Ok(records.into_iter().map(Image::from).collect())
+}
+
+/// List the images visible to the user one page at a time, continuing after `cursor`
+pub async fn list_images(
+    pool: &PgPool,
+    username: &str,
+    filter: &ImageFilter,
+    sort: ImageSort,
+    cursor: Option<&ImageCursor>,
+    limit: i64,
+) -> Result<Vec<Image>, sqlx::Error> {
+    // Asking for one user's images reaches a partner library that isn't in the timeline
+    let visibility = Visibility::load(pool, username, filter.owner.is_some()).await?;
+
+    let (key, descending) = match sort {
+        ImageSort::Newest => ("captured_at", true),
+        ImageSort::Oldest => ("captured_at", false),
+        ImageSort::NameAsc => ("COALESCE(image_name, '')", false),
+        ImageSort::NameDesc => ("COALESCE(image_name, '')", true),
+        ImageSort::RatingDesc => ("COALESCE(rating, 0)", true),
+    };
+    let direction = if descending { "DESC" } else { "ASC" };
+    let order = format!(" ORDER BY {key} {direction}, hash {direction} LIMIT ");
+
+    let mut query = QueryBuilder::<Postgres>::new("SELECT ");
+    query.push(IMAGE_COLUMNS);
+    query.push(" FROM ");
+    // Every branch is cut to one page, letting each walk its index
+    push_visible(&mut query, &visibility, |query| {
+        push_filter(query, filter);
+
+        // Keyset pagination, the hash breaks ties between equal sort values
+        if let Some(cursor) = cursor {
+            query.push(format!(
+                " AND ({key}, hash) {} (",
+                if descending { "<" } else { ">" }
+            ));
+            match &cursor.key {
+                CursorKey::Time(time) => query.push_bind(time.naive_utc()),
+                CursorKey::Text(text) => query.push_bind(text.clone()),
+                CursorKey::Number(number) => query.push_bind(*number),
+            };
+            query.push(", ");
+            query.push_bind(cursor.hash.clone());
+            query.push(")");
+        }
+
+        query.push(&order);
+        query.push_bind(limit);
+    });
+
+    query.push(&order);
+    query.push_bind(limit);
+
+    let records = query.build_query_as::<ImageRow>().fetch_all(pool).await?;
+
+    Ok(records.into_iter().map(Image::from).collect())
+}
+
+/// What a user can see, looked up ahead of the image queries so each branch
+/// of the visibility source can use an index
+pub(super) struct Visibility {
+    username: String,
+    /// Partner libraries with the capture time they are shared from
+    partners: Vec<(String, Option<NaiveDateTime>)>,
+    /// Albums the user owns or is a member of
+    albums: Vec<i64>,
+}
+
+impl Visibility {
+    /// The user's own images, the ones in albums shared with them and those of
+    /// partner libraries, only the partners merged into the timeline unless
+    /// `all_partners`
+    pub(super) async fn load(
+        pool: &PgPool,
+        username: &str,
+        all_partners: bool,
+    ) -> Result<Self, sqlx::Error> {
+        let partners = sqlx::query!(
+            r#"
+            SELECT owner, since
+            FROM partner_shares
+            WHERE partner = $1 AND ($2 OR in_timeline)
+            ORDER BY owner
+            "#,
+            username,
+            all_partners
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let albums = sqlx::query_scalar!(
+            r#"
+            SELECT id AS "id!" FROM albums WHERE owner = $1
+            UNION
+            SELECT album_id FROM album_members WHERE username = $1
+            "#,
+            username
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(Self {
+            username: username.to_string(),
+            partners: partners.into_iter().map(|p| (p.owner, p.since)).collect(),
+            albums,
+        })
+    }
+}
+
+/// Push the images `visibility` allows as the `images` source of a query:
+/// one branch per library, each served by the owner indexes, and one for the
+/// album images not already covered by them. Trashed images of other users
+/// stay hidden. `push_branch` continues the WHERE clause of every branch, so
+/// filters, ordering and limits apply before the branches are combined
+pub(super) fn push_visible<'args>(
+    query: &mut QueryBuilder<'args, Postgres>,
+    visibility: &Visibility,
+    mut push_branch: impl FnMut(&mut QueryBuilder<'args, Postgres>),
+) {
+    query.push("((SELECT * FROM images WHERE owner = ");
+    query.push_bind(visibility.username.clone());
+    push_branch(query);
+    query.push(")");
+
+    for (owner, since) in &visibility.partners {
+        query.push(" UNION ALL (SELECT * FROM images WHERE deleted_at IS NULL AND owner = ");
+        query.push_bind(owner.clone());
+        if let Some(since) = since {
+            query.push(" AND captured_at >= ");
+            query.push_bind(*since);
+        }
+        push_branch(query);
+        query.push(")");
+    }
+
+    if !visibility.albums.is_empty() {
+        query.push(
+            " UNION ALL (SELECT * FROM images WHERE deleted_at IS NULL \
+             AND hash IN (SELECT hash FROM album_images WHERE album_id = ANY(",
+        );
+        query.push_bind(visibility.albums.clone());
+        query.push(")) AND owner <> ");
+        query.push_bind(visibility.username.clone());
+        for (owner, since) in &visibility.partners {
+            query.push(" AND NOT (owner = ");
+            query.push_bind(owner.clone());
+            if let Some(since) = since {
+                query.push(" AND captured_at >= ");
+                query.push_bind(*since);
+            }
+            query.push(")");
+        }
+        push_branch(query);
+        query.push(")");
+    }
+
+    query.push(") images");
+}
+
+/// Continue a WHERE clause with the conditions of `filter`
+pub(super) fn push_filter(query: &mut QueryBuilder<'_, Postgres>, filter: &ImageFilter) {
+    if filter.trashed {
+        query.push(" AND deleted_at IS NOT NULL");
+    } else {
+        query.push(" AND deleted_at IS NULL");
+    }
+
+    if let Some(owner) = &filter.owner {
+        query.push(" AND owner = ");
+        query.push_bind(owner.clone());
+    }
+
+    if let Some(from) = filter.from {
+        query.push(" AND captured_at >= ");
+        query.push_bind(from.naive_utc());
+    }
+
+    if let Some(to) = filter.to {
+        query.push(" AND captured_at < ");
+        query.push_bind(to.naive_utc());
+    }
+
+    if let Some(media_type) = filter.media_type {
+        query.push(" AND media_type = ");
+        query.push_bind(media_type.as_str());
+    }
+
+    for tag in &filter.tags {
+        query.push(
+            " AND EXISTS (SELECT 1 FROM image_tags t WHERE t.hash = images.hash AND t.owner = images.owner AND t.tag = ",
+        );
+        query.push_bind(tag.clone());
+        query.push(")");
+    }
+
+    if let Some(favorite) = filter.favorite {
+        query.push(" AND favorite = ");
+        query.push_bind(favorite);
+    }
+
+    if let Some(min_rating) = filter.min_rating {
+        query.push(" AND rating >= ");
+        query.push_bind(min_rating);
+    }
+
+    // Substring matches, served by the trigram indexes
+    if let Some(name) = &filter.name {
+        query.push(" AND image_name ILIKE ");
+        query.push_bind(like_pattern(name));
+    }
+
+    if let Some(camera_model) = &filter.camera_model {
+        query.push(" AND camera_model ILIKE ");
+        query.push_bind(like_pattern(camera_model));
+    }
+
+    match filter.has_location {
+        Some(true) => query.push(" AND effective_latitude IS NOT NULL"),
+        Some(false) => query.push(" AND effective_latitude IS NULL"),
+        None => query,
+    };
+
+    if let Some(bbox) = &filter.bbox {
+        // Matches the expression of the GiST index
+        query.push(" AND effective_latitude IS NOT NULL AND (");
+        for (i, (min_longitude, max_longitude)) in bbox.longitude_ranges().into_iter().enumerate() {
+            if i > 0 {
+                query.push(" OR ");
+            }
+            query.push("point(effective_longitude, effective_latitude) <@ box(point(");
+            query.push_bind(min_longitude);
+            query.push(", ");
+            query.push_bind(bbox.min_latitude);
+            query.push("), point(");
+            query.push_bind(max_longitude);
+            query.push(", ");
+            query.push_bind(bbox.max_latitude);
+            query.push("))");
+        }
+        query.push(")");
+    }
+
+    for (column, value) in [
+        ("country", &filter.country),
+        ("region", &filter.region),
+        ("city", &filter.city),
+    ] {
+        if let Some(value) = value {
+            query.push(format!(" AND {column} = "));
+            query.push_bind(value.clone());
+        }
+    }
+
+    if let Some(album_id) = filter.album_id {
+        query.push(
+            " AND EXISTS (SELECT 1 FROM album_images ai WHERE ai.hash = images.hash AND ai.owner = images.owner AND ai.album_id = ",
+        );
+        query.push_bind(album_id);
+        query.push(")");
+    }
+}
+
+/// `%text%` with the LIKE wildcards in `text` escaped
+fn like_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!("%{escaped}%")
 }
 
 pub async fn delete_image(pool: &PgPool, hash: &str, owner: &str) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
     let result = sqlx::query!(
         r#"
         DELETE FROM images
@@ -87,8 +665,16 @@ pub async fn delete_image(pool: &PgPool, hash: &str, owner: &str) -> Result<bool
         hash,
         owner
     )
-    .execute(pool)
+    .execute(&mut *tx)
     .await?;
 
-    Ok(result.rows_affected() > 0)
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    record_change(&mut tx, owner, hash, ChangeKind::Deleted).await?;
+
+    tx.commit().await?;
+
+    Ok(true)
 }
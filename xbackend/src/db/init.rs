@@ -12,5 +12,11 @@ pub async fn init() -> PgPool {
 
     println!("Database connected successfully!");
 
+    // Only creates what is missing, older databases are brought up to date
+    sqlx::raw_sql(include_str!("../../db.sql"))
+        .execute(&pool)
+        .await
+        .expect("Failed to apply db.sql");
+
     pool
 }
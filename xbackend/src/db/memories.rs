@@ -0,0 +1,73 @@
+use super::images::{IMAGE_COLUMNS, ImageRow, Visibility, push_visible};
+use super::timeline::push_local_time;
+use crate::types::Image;
+use chrono::{Datelike, NaiveDate};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+
+#[derive(FromRow)]
+struct MemoryRow {
+    #[sqlx(flatten)]
+    image: ImageRow,
+    year: i32,
+}
+
+/// Images captured on the same local calendar day as `date` in earlier years,
+/// at most `per_year` of them for each year, most recent year first, out of
+/// the images visible in the user's timeline. Feb 29 photos
+/// show up on Feb 28 in years without a leap day
+pub async fn get_memories(
+    pool: &PgPool,
+    username: &str,
+    date: NaiveDate,
+    timezone: &str,
+    per_year: i64,
+) -> Result<Vec<(i32, Image)>, sqlx::Error> {
+    let visibility = Visibility::load(pool, username, false).await?;
+
+    let mut query = QueryBuilder::<Postgres>::new(format!(
+        "SELECT {IMAGE_COLUMNS}, year FROM (\
+         SELECT *, ROW_NUMBER() OVER (PARTITION BY year ORDER BY captured_at, hash) AS rank FROM (\
+         SELECT *, EXTRACT(YEAR FROM local_time)::INTEGER AS year FROM (SELECT *, "
+    ));
+    push_local_time(&mut query, timezone);
+    query.push(" AS local_time FROM ");
+    push_visible(&mut query, &visibility, |query| {
+        query.push(" AND deleted_at IS NULL");
+    });
+
+    query.push(") localized WHERE ((EXTRACT(MONTH FROM local_time) = ");
+    query.push_bind(date.month() as i32);
+    query.push(" AND EXTRACT(DAY FROM local_time) = ");
+    query.push_bind(date.day() as i32);
+    query.push(")");
+    if date.month() == 2 && date.day() == 28 && date.with_day(29).is_none() {
+        query
+            .push(" OR (EXTRACT(MONTH FROM local_time) = 2 AND EXTRACT(DAY FROM local_time) = 29)");
+    }
+    query.push(") AND EXTRACT(YEAR FROM local_time) < ");
+    query.push_bind(date.year());
+    query.push(") same_day) ranked WHERE rank <= ");
+    query.push_bind(per_year);
+    query.push(" ORDER BY year DESC, captured_at, hash");
+
+    let records = query.build_query_as::<MemoryRow>().fetch_all(pool).await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| (r.year, Image::from(r.image)))
+        .collect())
+}
+
+/// Today's date in the given timezone
+pub async fn get_local_date(pool: &PgPool, timezone: &str) -> Result<NaiveDate, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT (NOW() AT TIME ZONE $1)::DATE AS "date!"
+        "#,
+        timezone
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(record.date)
+}
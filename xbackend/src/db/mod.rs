@@ -1,7 +1,57 @@
+mod albums;
+mod api_tokens;
+mod batch;
+mod changes;
+mod devices;
+mod events;
+mod geo;
 mod images;
 mod init;
+mod memories;
+mod partners;
+mod places;
+mod sessions;
+mod shares;
+mod tags;
+mod timeline;
 mod users;
 
-pub use images::{delete_image, get_image_by_hash, get_image_hashes_by_owner, insert_image};
+pub use albums::{
+    add_album_images, create_album, delete_album, get_album, get_album_image, get_album_images,
+    get_album_members, get_albums, remove_album_images, remove_album_member, reorder_album,
+    set_album_member, update_album,
+};
+pub use api_tokens::{authenticate_api_token, create_api_token, get_api_tokens, revoke_api_token};
+pub use batch::apply_batch;
+pub use changes::{get_changes_since, listen_changes};
+pub use devices::{
+    create_device, delete_device, get_device, get_device_files, get_devices, record_source,
+    record_sync,
+};
+pub use events::{
+    EventImage, NewEvent, get_changed_span, get_event, get_event_backlog, get_event_candidates,
+    get_event_images, get_event_span, get_events, get_gap_span, get_replaced_events,
+    replace_events, set_event_cursor,
+};
+pub use geo::{get_images_near, get_map_clusters};
+pub use images::{
+    delete_image, exceeds_quota, get_existing_hashes, get_image_by_hash, get_image_hashes_by_owner,
+    get_unsized_images, insert_image, list_images, set_image_size, update_image,
+};
 pub use init::init;
-pub use users::{UserError, validate_user};
+pub use memories::{get_local_date, get_memories};
+pub use partners::{
+    delete_partner_share, get_partner_shares, set_partner_in_timeline, set_partner_share,
+};
+pub use places::{get_pending_locations, get_place_groups, set_places};
+pub use sessions::{
+    Rotation, check_session, create_session, delete_session, delete_sessions, get_sessions,
+    rotate_session,
+};
+pub use shares::{create_share_link, get_active_share_link, get_share_links, revoke_share_link};
+pub use tags::{add_image_tags, get_image_tags, get_tag_counts, normalize_tag, remove_image_tag};
+pub use timeline::{get_timeline, is_valid_timezone};
+pub use users::{
+    UserError, create_invite, create_user, delete_user, get_user_summaries, is_admin, rename_user,
+    set_admin, set_password, set_quota, validate_user,
+};
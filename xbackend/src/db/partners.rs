@@ -0,0 +1,118 @@
+use crate::types::PartnerShare;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::PgPool;
+
+struct PartnerShareRow {
+    owner: String,
+    partner: String,
+    since: Option<NaiveDateTime>,
+    in_timeline: bool,
+    created_at: Option<NaiveDateTime>,
+}
+
+impl From<PartnerShareRow> for PartnerShare {
+    fn from(r: PartnerShareRow) -> Self {
+        PartnerShare {
+            owner: r.owner,
+            partner: r.partner,
+            since: r.since.map(|t| t.and_utc()),
+            in_timeline: r.in_timeline,
+            created_at: r
+                .created_at
+                .unwrap_or_else(|| chrono::Utc::now().naive_utc())
+                .and_utc(),
+        }
+    }
+}
+
+/// Grant the partner access or move the start date of an existing grant,
+/// fails with a foreign key violation for unknown users
+pub async fn set_partner_share(
+    pool: &PgPool,
+    owner: &str,
+    partner: &str,
+    since: Option<DateTime<Utc>>,
+) -> Result<PartnerShare, sqlx::Error> {
+    let record = sqlx::query_as!(
+        PartnerShareRow,
+        r#"
+        INSERT INTO partner_shares (owner, partner, since)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (owner, partner) DO UPDATE SET since = EXCLUDED.since
+        RETURNING owner, partner, since, in_timeline, created_at
+        "#,
+        owner,
+        partner,
+        since.map(|t| t.naive_utc())
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(PartnerShare::from(record))
+}
+
+/// Shares the user granted and the ones granted to them
+pub async fn get_partner_shares(
+    pool: &PgPool,
+    username: &str,
+) -> Result<Vec<PartnerShare>, sqlx::Error> {
+    let records = sqlx::query_as!(
+        PartnerShareRow,
+        r#"
+        SELECT owner, partner, since, in_timeline, created_at
+        FROM partner_shares
+        WHERE owner = $1 OR partner = $1
+        ORDER BY created_at, owner, partner
+        "#,
+        username
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(PartnerShare::from).collect())
+}
+
+/// Set by the partner, returns `None` if the owner doesn't share with them
+pub async fn set_partner_in_timeline(
+    pool: &PgPool,
+    owner: &str,
+    partner: &str,
+    in_timeline: bool,
+) -> Result<Option<PartnerShare>, sqlx::Error> {
+    let record = sqlx::query_as!(
+        PartnerShareRow,
+        r#"
+        UPDATE partner_shares
+        SET in_timeline = $3
+        WHERE owner = $1 AND partner = $2
+        RETURNING owner, partner, since, in_timeline, created_at
+        "#,
+        owner,
+        partner,
+        in_timeline
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(PartnerShare::from))
+}
+
+/// Either side can end the share
+pub async fn delete_partner_share(
+    pool: &PgPool,
+    owner: &str,
+    partner: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM partner_shares
+        WHERE owner = $1 AND partner = $2
+        "#,
+        owner,
+        partner
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,123 @@
+use super::images::{Visibility, push_visible};
+use crate::types::{ChangeKind, Place, PlaceGroup, PlaceLevel};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+
+/// Geotagged image whose place hasn't been resolved yet
+pub struct PendingLocation {
+    pub hash: String,
+    pub owner: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Images of every user waiting for the geocoder, oldest uploads first
+pub async fn get_pending_locations(
+    pool: &PgPool,
+    limit: i64,
+) -> Result<Vec<PendingLocation>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingLocation,
+        r#"
+        SELECT hash, owner AS "owner!", effective_latitude AS "latitude!",
+               effective_longitude AS "longitude!"
+        FROM images
+        WHERE geocoded_at IS NULL AND effective_latitude IS NOT NULL
+        ORDER BY created_at
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Store the resolved places and journal the images that got one,
+/// images whose location changed in the meantime stay pending.
+/// Returns the journal cursor of the written entries, if any
+pub async fn set_places(
+    pool: &PgPool,
+    resolved: &[(PendingLocation, Option<Place>)],
+) -> Result<Option<i64>, sqlx::Error> {
+    let hashes: Vec<String> = resolved.iter().map(|(p, _)| p.hash.clone()).collect();
+    let owners: Vec<String> = resolved.iter().map(|(p, _)| p.owner.clone()).collect();
+    let latitudes: Vec<f64> = resolved.iter().map(|(p, _)| p.latitude).collect();
+    let longitudes: Vec<f64> = resolved.iter().map(|(p, _)| p.longitude).collect();
+    let countries: Vec<Option<String>> = resolved
+        .iter()
+        .map(|(_, place)| place.as_ref().map(|p| p.country.clone()))
+        .collect();
+    let regions: Vec<Option<String>> = resolved
+        .iter()
+        .map(|(_, place)| place.as_ref().and_then(|p| p.region.clone()))
+        .collect();
+    let cities: Vec<Option<String>> = resolved
+        .iter()
+        .map(|(_, place)| place.as_ref().map(|p| p.city.clone()))
+        .collect();
+
+    sqlx::query!(
+        r#"
+        WITH updated AS (
+            UPDATE images i
+            SET country = r.country,
+                region = r.region,
+                city = r.city,
+                geocoded_at = $8
+            FROM UNNEST($1::VARCHAR[], $2::VARCHAR[], $3::FLOAT8[], $4::FLOAT8[],
+                        $5::VARCHAR[], $6::VARCHAR[], $7::VARCHAR[])
+                 AS r(hash, owner, latitude, longitude, country, region, city)
+            WHERE i.hash = r.hash
+              AND i.owner = r.owner
+              AND i.geocoded_at IS NULL
+              AND i.effective_latitude = r.latitude
+              AND i.effective_longitude = r.longitude
+            RETURNING i.owner, i.hash, i.country
+        ),
+        -- Nothing changed for clients when no place was found
+        journaled AS (
+            INSERT INTO image_changes (owner, hash, kind)
+            SELECT owner, hash, $9
+            FROM updated
+            WHERE country IS NOT NULL
+            RETURNING xact_id
+        )
+        SELECT MAX(xact_id) AS cursor FROM journaled
+        "#,
+        &hashes,
+        &owners,
+        &latitudes,
+        &longitudes,
+        &countries as &[Option<String>],
+        &regions as &[Option<String>],
+        &cities as &[Option<String>],
+        chrono::Utc::now().naive_utc(),
+        ChangeKind::Updated.as_str()
+    )
+    .fetch_one(pool)
+    .await
+    .map(|row| row.cursor)
+}
+
+/// The images visible to the user grouped by place, largest groups first
+pub async fn get_place_groups(
+    pool: &PgPool,
+    username: &str,
+    level: PlaceLevel,
+) -> Result<Vec<PlaceGroup>, sqlx::Error> {
+    let visibility = Visibility::load(pool, username, false).await?;
+
+    let mut query = QueryBuilder::<Postgres>::new("SELECT country, CASE WHEN ");
+    query.push_bind(level.as_str());
+    query.push(" <> 'country' THEN region END AS region, CASE WHEN ");
+    query.push_bind(level.as_str());
+    query.push(
+        " = 'city' THEN city END AS city, COUNT(*) AS count, \
+         (ARRAY_AGG(hash ORDER BY captured_at DESC, hash DESC))[1] AS hash FROM ",
+    );
+    push_visible(&mut query, &visibility, |query| {
+        query.push(" AND deleted_at IS NULL AND country IS NOT NULL");
+    });
+    query.push(" GROUP BY 1, 2, 3 ORDER BY COUNT(*) DESC, 1, 2, 3");
+
+    query.build_query_as::<PlaceGroup>().fetch_all(pool).await
+}
@@ -0,0 +1,214 @@
+use crate::types::Session;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use sqlx::PgPool;
+
+struct SessionRow {
+    id: i64,
+    device_name: Option<String>,
+    created_at: NaiveDateTime,
+    last_used_at: NaiveDateTime,
+    expires_at: NaiveDateTime,
+}
+
+impl From<SessionRow> for Session {
+    fn from(r: SessionRow) -> Self {
+        Session {
+            id: r.id,
+            device_name: r.device_name,
+            created_at: r.created_at.and_utc(),
+            last_used_at: r.last_used_at.and_utc(),
+            expires_at: r.expires_at.and_utc(),
+        }
+    }
+}
+
+/// What presenting a refresh token did
+pub enum Rotation {
+    /// The token was current and has been replaced
+    Rotated { id: i64, username: String },
+    /// The token had already been replaced, the session is gone now
+    Reused,
+    /// Unknown or expired
+    Invalid,
+}
+
+/// Start a session, returns its id. Expired sessions of the user are cleared
+/// out on the way.
+pub async fn create_session(
+    pool: &PgPool,
+    username: &str,
+    device_name: Option<&str>,
+    refresh_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<i64, sqlx::Error> {
+    let now = Utc::now().naive_utc();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE username = $1 AND expires_at <= $2
+        "#,
+        username,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO sessions (username, device_name, refresh_hash, created_at, last_used_at,
+                              expires_at)
+        VALUES ($1, $2, $3, $4, $4, $5)
+        RETURNING id
+        "#,
+        username,
+        device_name,
+        refresh_hash,
+        now,
+        expires_at.naive_utc()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(id)
+}
+
+/// Swap the session's refresh token for a new one. A token that was already
+/// swapped means it leaked or the client is confused, either way the session
+/// is ended. Within `retry_window` of the swap it is swapped again instead, the
+/// client may have retried after losing the response.
+pub async fn rotate_session(
+    pool: &PgPool,
+    refresh_hash: &str,
+    new_refresh_hash: &str,
+    expires_at: DateTime<Utc>,
+    retry_window: Duration,
+) -> Result<Rotation, sqlx::Error> {
+    let now = Utc::now().naive_utc();
+
+    // The token handed out by the lost response is dropped on a retry
+    let record = sqlx::query!(
+        r#"
+        UPDATE sessions
+        SET refresh_hash = $2,
+            previous_refresh_hash = CASE WHEN refresh_hash = $1 THEN refresh_hash
+                                         ELSE previous_refresh_hash END,
+            rotated_at = CASE WHEN refresh_hash = $1 THEN $3 ELSE rotated_at END,
+            last_used_at = $3, expires_at = $4
+        WHERE (refresh_hash = $1 OR (previous_refresh_hash = $1 AND rotated_at > $3::TIMESTAMP - $5::INTERVAL))
+          AND expires_at > $3
+        RETURNING id, username
+        "#,
+        refresh_hash,
+        new_refresh_hash,
+        now,
+        expires_at.naive_utc(),
+        retry_window as _
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some(r) = record {
+        return Ok(Rotation::Rotated {
+            id: r.id,
+            username: r.username,
+        });
+    }
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE previous_refresh_hash = $1
+        "#,
+        refresh_hash
+    )
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        Ok(Rotation::Reused)
+    } else {
+        Ok(Rotation::Invalid)
+    }
+}
+
+/// Whether the session is still open, noting that it was used
+pub async fn check_session(pool: &PgPool, id: i64, username: &str) -> Result<bool, sqlx::Error> {
+    let now = Utc::now().naive_utc();
+
+    // Only written once a minute, most requests just read
+    let valid = sqlx::query_scalar!(
+        r#"
+        WITH session AS (
+            SELECT id, last_used_at
+            FROM sessions
+            WHERE id = $1 AND username = $2 AND expires_at > $3
+        ),
+        touched AS (
+            UPDATE sessions
+            SET last_used_at = $3
+            WHERE id = (SELECT id FROM session) AND last_used_at < $3 - INTERVAL '1 minute'
+        )
+        SELECT EXISTS (SELECT 1 FROM session) AS "valid!"
+        "#,
+        id,
+        username,
+        now
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(valid)
+}
+
+/// The user's open sessions, most recently used first
+pub async fn get_sessions(pool: &PgPool, username: &str) -> Result<Vec<Session>, sqlx::Error> {
+    let records = sqlx::query_as!(
+        SessionRow,
+        r#"
+        SELECT id, device_name, created_at, last_used_at, expires_at
+        FROM sessions
+        WHERE username = $1 AND expires_at > $2
+        ORDER BY last_used_at DESC, id DESC
+        "#,
+        username,
+        Utc::now().naive_utc()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(Session::from).collect())
+}
+
+pub async fn delete_session(pool: &PgPool, id: i64, username: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE id = $1 AND username = $2
+        "#,
+        id,
+        username
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Log the user out everywhere
+pub async fn delete_sessions(pool: &PgPool, username: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE username = $1
+        "#,
+        username
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
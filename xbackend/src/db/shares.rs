@@ -0,0 +1,131 @@
+use crate::types::{ShareLink, ShareTarget};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::PgPool;
+
+struct ShareLinkRow {
+    id: i64,
+    owner: String,
+    image_hash: Option<String>,
+    album_id: Option<i64>,
+    password: Option<String>,
+    allow_download: bool,
+    expires_at: Option<NaiveDateTime>,
+    created_at: Option<NaiveDateTime>,
+    revoked_at: Option<NaiveDateTime>,
+}
+
+impl From<ShareLinkRow> for ShareLink {
+    fn from(r: ShareLinkRow) -> Self {
+        ShareLink {
+            id: r.id,
+            owner: r.owner,
+            image_hash: r.image_hash,
+            album_id: r.album_id,
+            password: r.password,
+            allow_download: r.allow_download,
+            expires_at: r.expires_at.map(|t| t.and_utc()),
+            created_at: r
+                .created_at
+                .unwrap_or_else(|| chrono::Utc::now().naive_utc())
+                .and_utc(),
+            revoked_at: r.revoked_at.map(|t| t.and_utc()),
+        }
+    }
+}
+
+/// Fails with a foreign key violation if the target isn't one of the owner's
+pub async fn create_share_link(
+    pool: &PgPool,
+    owner: &str,
+    token_hash: &str,
+    target: &ShareTarget,
+    password: Option<&str>,
+    allow_download: bool,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<ShareLink, sqlx::Error> {
+    let (image_hash, album_id) = match target {
+        ShareTarget::Image(hash) => (Some(hash.as_str()), None),
+        ShareTarget::Album(id) => (None, Some(*id)),
+    };
+
+    let record = sqlx::query_as!(
+        ShareLinkRow,
+        r#"
+        INSERT INTO share_links (owner, token_hash, image_hash, album_id, password, allow_download,
+                                 expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, owner, image_hash, album_id, password, allow_download, expires_at,
+                  created_at, revoked_at
+        "#,
+        owner,
+        token_hash,
+        image_hash,
+        album_id,
+        password,
+        allow_download,
+        expires_at.map(|t| t.naive_utc())
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(ShareLink::from(record))
+}
+
+pub async fn get_share_links(pool: &PgPool, owner: &str) -> Result<Vec<ShareLink>, sqlx::Error> {
+    let records = sqlx::query_as!(
+        ShareLinkRow,
+        r#"
+        SELECT id, owner, image_hash, album_id, password, allow_download, expires_at,
+               created_at, revoked_at
+        FROM share_links
+        WHERE owner = $1
+        ORDER BY created_at DESC, id DESC
+        "#,
+        owner
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(ShareLink::from).collect())
+}
+
+/// A link that is neither revoked nor expired
+pub async fn get_active_share_link(
+    pool: &PgPool,
+    token_hash: &str,
+) -> Result<Option<ShareLink>, sqlx::Error> {
+    let record = sqlx::query_as!(
+        ShareLinkRow,
+        r#"
+        SELECT id, owner, image_hash, album_id, password, allow_download, expires_at,
+               created_at, revoked_at
+        FROM share_links
+        WHERE token_hash = $1
+          AND revoked_at IS NULL
+          AND (expires_at IS NULL OR expires_at > $2)
+        "#,
+        token_hash,
+        chrono::Utc::now().naive_utc()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(ShareLink::from))
+}
+
+pub async fn revoke_share_link(pool: &PgPool, id: i64, owner: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE share_links
+        SET revoked_at = $3
+        WHERE id = $1 AND owner = $2 AND revoked_at IS NULL
+        "#,
+        id,
+        owner,
+        chrono::Utc::now().naive_utc()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,120 @@
+use super::changes::record_change;
+use crate::types::{ChangeKind, TagCount};
+use sqlx::PgPool;
+
+const MAX_TAG_LENGTH: usize = 64;
+
+/// Tags are matched case-insensitively, so they are stored trimmed and lowercase
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.split_whitespace().collect::<Vec<_>>().join(" ");
+    let tag = tag.to_lowercase();
+
+    if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH {
+        return None;
+    }
+
+    Some(tag)
+}
+
+pub async fn get_image_tags(
+    pool: &PgPool,
+    hash: &str,
+    owner: &str,
+) -> Result<Vec<String>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT tag
+        FROM image_tags
+        WHERE hash = $1 AND owner = $2
+        ORDER BY tag
+        "#,
+        hash,
+        owner
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(|r| r.tag).collect())
+}
+
+/// Attach tags to an image, tags it already has are left alone
+pub async fn add_image_tags(
+    pool: &PgPool,
+    hash: &str,
+    owner: &str,
+    tags: &[String],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO image_tags (hash, owner, tag)
+        SELECT $1, $2, tag
+        FROM UNNEST($3::VARCHAR[]) AS tag
+        ON CONFLICT DO NOTHING
+        "#,
+        hash,
+        owner,
+        tags
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        record_change(&mut tx, owner, hash, ChangeKind::Updated).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn remove_image_tag(
+    pool: &PgPool,
+    hash: &str,
+    owner: &str,
+    tag: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM image_tags
+        WHERE hash = $1 AND owner = $2 AND tag = $3
+        "#,
+        hash,
+        owner,
+        tag
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    record_change(&mut tx, owner, hash, ChangeKind::Updated).await?;
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// The owner's tag vocabulary with the number of images in the library using each tag
+pub async fn get_tag_counts(pool: &PgPool, owner: &str) -> Result<Vec<TagCount>, sqlx::Error> {
+    let records = sqlx::query_as!(
+        TagCount,
+        r#"
+        SELECT t.tag, COUNT(*) AS "count!"
+        FROM image_tags t
+        JOIN images i ON i.hash = t.hash AND i.owner = t.owner
+        WHERE t.owner = $1 AND i.deleted_at IS NULL
+        GROUP BY t.tag
+        ORDER BY COUNT(*) DESC, t.tag
+        "#,
+        owner
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records)
+}
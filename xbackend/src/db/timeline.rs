@@ -0,0 +1,63 @@
+use super::images::{Visibility, push_filter, push_visible};
+use crate::types::{ImageFilter, TimelineBucket, TimelineGranularity};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use std::collections::HashSet;
+use tokio::sync::OnceCell;
+
+/// Timezone names known to Postgres, they only change with a server upgrade
+static TIMEZONES: OnceCell<HashSet<String>> = OnceCell::const_new();
+
+/// Whether Postgres knows the IANA timezone name, the names are loaded once
+pub async fn is_valid_timezone(pool: &PgPool, timezone: &str) -> Result<bool, sqlx::Error> {
+    let timezones = TIMEZONES
+        .get_or_try_init(|| async {
+            let names = sqlx::query_scalar!(r#"SELECT name AS "name!" FROM pg_timezone_names"#)
+                .fetch_all(pool)
+                .await?;
+
+            Ok::<_, sqlx::Error>(names.into_iter().collect())
+        })
+        .await?;
+
+    Ok(timezones.contains(timezone))
+}
+
+/// Push the capture time of an image as local time in `timezone`. A camera
+/// time without a recorded offset is already local and used as it is, every
+/// other capture time is a UTC instant stored without a zone
+pub(super) fn push_local_time<'args>(
+    query: &mut QueryBuilder<'args, Postgres>,
+    timezone: &'args str,
+) {
+    query.push(
+        "CASE WHEN taken_at_override IS NULL AND taken_at IS NOT NULL AND taken_at_offset IS NULL \
+         THEN captured_at ELSE (captured_at AT TIME ZONE 'UTC') AT TIME ZONE ",
+    );
+    query.push_bind(timezone);
+    query.push(" END");
+}
+
+/// Image counts per period of local capture time, newest first
+pub async fn get_timeline(
+    pool: &PgPool,
+    username: &str,
+    filter: &ImageFilter,
+    granularity: TimelineGranularity,
+    timezone: &str,
+) -> Result<Vec<TimelineBucket>, sqlx::Error> {
+    let visibility = Visibility::load(pool, username, filter.owner.is_some()).await?;
+
+    let mut query = QueryBuilder::<Postgres>::new("SELECT date_trunc(");
+    query.push_bind(granularity.as_str());
+    query.push(", ");
+    push_local_time(&mut query, timezone);
+    query.push(")::date AS date, COUNT(*) AS count FROM ");
+    push_visible(&mut query, &visibility, |query| push_filter(query, filter));
+
+    query.push(" GROUP BY 1 ORDER BY 1 DESC");
+
+    query
+        .build_query_as::<TimelineBucket>()
+        .fetch_all(pool)
+        .await
+}
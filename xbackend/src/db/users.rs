@@ -1,21 +1,17 @@
+use crate::secret::{hash_password, verify_password};
 use crate::types::{User, UserCredentials};
-use argon2::{
-    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
-    password_hash::{SaltString, rand_core::OsRng},
-};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use sqlx::PgPool;
 
 #[derive(Debug)]
 pub enum UserError {
-    #[allow(dead_code)]
     UsernameExists,
     InvalidCredentials,
+    InvalidInvite, // unknown, used or expired
     #[allow(dead_code)]
     DatabaseError(sqlx::Error),
     #[allow(dead_code)]
     HashError(argon2::password_hash::Error),
-    #[allow(dead_code)]
-    ParamError(String),
 }
 
 impl From<sqlx::Error> for UserError {
@@ -30,41 +26,106 @@ impl From<argon2::password_hash::Error> for UserError {
     }
 }
 
-/// Create a new user with a hashed password
-#[allow(dead_code)]
-pub async fn create_user(pool: &PgPool, credentials: &UserCredentials) -> Result<User, UserError> {
-    // Hash the password using Argon2id with custom parameters
-    let salt = SaltString::generate(&mut OsRng);
+/// Create a new user with a hashed password, using up the invite code if one is given
+pub async fn create_user(
+    pool: &PgPool,
+    credentials: &UserCredentials,
+    invite_hash: Option<&str>,
+) -> Result<User, UserError> {
+    let mut tx = pool.begin().await?;
 
-    let params =
-        Params::new(65536, 2, 2, None).map_err(|e| UserError::ParamError(e.to_string()))?;
+    // Checked before the username so the codes can't be used to probe for accounts
+    if let Some(invite_hash) = invite_hash {
+        let invite = sqlx::query!(
+            r#"
+            SELECT code_hash
+            FROM invite_codes
+            WHERE code_hash = $1 AND used_by IS NULL
+              AND (expires_at IS NULL OR expires_at > NOW() AT TIME ZONE 'UTC')
+            FOR UPDATE
+            "#,
+            invite_hash
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
 
-    let argon2 = Argon2::new(
-        Algorithm::Argon2id, // Use Argon2id variant (recommended)
-        Version::V0x13,      // Use version 1.3
-        params,
-    );
+        if invite.is_none() {
+            return Err(UserError::InvalidInvite);
+        }
+    }
 
-    let password_hash = argon2
-        .hash_password(credentials.password.as_bytes(), &salt)?
-        .to_string();
+    // Only hashed once the invite holds up, guessing codes shouldn't cost the server a hash each
+    let password_hash = hash_password(&credentials.password)?;
 
     // Insert user into database and return the created use
     let result = sqlx::query_as::<_, User>(
-        "INSERT INTO users (username, password) VALUES ($1, $2) RETURNING username, password, created_at"
+        "INSERT INTO users (username, password) VALUES ($1, $2) RETURNING username, password, created_at, is_admin"
     )
     .bind(&credentials.username)
     .bind(&password_hash)
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await;
 
-    match result {
-        Ok(user) => Ok(user),
+    let user = match result {
+        Ok(user) => user,
         Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
-            Err(UserError::UsernameExists)
+            return Err(UserError::UsernameExists);
         }
-        Err(e) => Err(UserError::DatabaseError(e)),
+        Err(e) => return Err(UserError::DatabaseError(e)),
+    };
+
+    if let Some(invite_hash) = invite_hash {
+        sqlx::query!(
+            r#"
+            UPDATE invite_codes
+            SET used_by = $2, used_at = NOW() AT TIME ZONE 'UTC'
+            WHERE code_hash = $1
+            "#,
+            invite_hash,
+            user.username
+        )
+        .execute(&mut *tx)
+        .await?;
     }
+
+    tx.commit().await?;
+
+    Ok(user)
+}
+
+pub async fn is_admin(pool: &PgPool, username: &str) -> Result<bool, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM users WHERE username = $1 AND is_admin) AS "admin!"
+        "#,
+        username
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(record.admin)
+}
+
+/// Store a new invite code, only its hash
+pub async fn create_invite(
+    pool: &PgPool,
+    created_by: Option<&str>,
+    code_hash: &str,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO invite_codes (code_hash, created_by, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        code_hash,
+        created_by,
+        expires_at.map(|t| t.naive_utc())
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
 }
 
 /// Validate a user's credentials
@@ -73,30 +134,203 @@ pub async fn validate_user(
     credentials: &UserCredentials,
 ) -> Result<User, UserError> {
     // Retrieve the user from database
-    let user: Option<User> =
-        sqlx::query_as("SELECT username, password, created_at FROM users WHERE username = $1")
-            .bind(&credentials.username)
-            .fetch_optional(pool)
-            .await?;
+    let user: Option<User> = sqlx::query_as(
+        "SELECT username, password, created_at, is_admin FROM users WHERE username = $1",
+    )
+    .bind(&credentials.username)
+    .fetch_optional(pool)
+    .await?;
 
     match user {
         Some(user) => {
             // Verify the password against the Argon2id hash
-            let parsed_hash = PasswordHash::new(&user.password)?;
+            if verify_password(&credentials.password, &user.password)? {
+                Ok(user)
+            } else {
+                Err(UserError::InvalidCredentials)
+            }
+        }
+        None => Err(UserError::InvalidCredentials),
+    }
+}
 
-            // Configure Argon2 with the same parameters for verification
-            let params =
-                Params::new(19456, 2, 1, None).map_err(|e| UserError::ParamError(e.to_string()))?;
+/// A user and how much storage they use, for the admin command
+pub struct UserSummary {
+    pub username: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub is_admin: bool,
+    pub quota_bytes: Option<i64>,
+    pub image_count: i64,
+    pub used_bytes: i64,
+}
 
-            let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+pub async fn get_user_summaries(pool: &PgPool) -> Result<Vec<UserSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        UserSummary,
+        r#"
+        SELECT u.username, u.created_at, u.is_admin, u.quota_bytes,
+               COUNT(i.hash) AS "image_count!",
+               COALESCE(SUM(i.size_bytes), 0)::BIGINT AS "used_bytes!"
+        FROM users u
+        LEFT JOIN images i ON i.owner = u.username
+        GROUP BY u.username
+        ORDER BY u.username
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
 
-            let result = argon2.verify_password(credentials.password.as_bytes(), &parsed_hash);
+/// Delete the user and everything they own, returns the file names of their
+/// images so they can be removed from storage, or `None` for an unknown user
+pub async fn delete_user(
+    pool: &PgPool,
+    username: &str,
+) -> Result<Option<Vec<String>>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
 
-            match result {
-                Ok(_) => Ok(user),
-                Err(_) => Err(UserError::InvalidCredentials),
-            }
+    let files = sqlx::query!(
+        r#"
+        SELECT hash, extension
+        FROM images
+        WHERE owner = $1
+        "#,
+        username
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|r| format!("{}.{}", r.hash, r.extension.unwrap_or_default()))
+    .collect();
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM users
+        WHERE username = $1
+        "#,
+        username
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    tx.commit().await?;
+
+    Ok(Some(files))
+}
+
+/// Everything the user owns follows the new name
+pub async fn rename_user(
+    pool: &PgPool,
+    username: &str,
+    new_username: &str,
+) -> Result<bool, UserError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE users
+        SET username = $2
+        WHERE username = $1
+        "#,
+        username,
+        new_username
+    )
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(result) => Ok(result.rows_affected() > 0),
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            Err(UserError::UsernameExists)
         }
-        None => Err(UserError::InvalidCredentials),
+        Err(e) => Err(UserError::DatabaseError(e)),
     }
 }
+
+/// Also ends all of the user's sessions and revokes their API tokens, so a
+/// reset locks out whoever had the account
+pub async fn set_password(
+    pool: &PgPool,
+    username: &str,
+    password: &str,
+) -> Result<bool, UserError> {
+    let password_hash = hash_password(password)?;
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE users
+        SET password = $2
+        WHERE username = $1
+        "#,
+        username,
+        password_hash
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE username = $1
+        "#,
+        username
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = $2
+        WHERE owner = $1 AND revoked_at IS NULL
+        "#,
+        username,
+        Utc::now().naive_utc()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// `None` removes the limit
+pub async fn set_quota(
+    pool: &PgPool,
+    username: &str,
+    quota_bytes: Option<i64>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE users
+        SET quota_bytes = $2
+        WHERE username = $1
+        "#,
+        username,
+        quota_bytes
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn set_admin(pool: &PgPool, username: &str, is_admin: bool) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE users
+        SET is_admin = $2
+        WHERE username = $1
+        "#,
+        username,
+        is_admin
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
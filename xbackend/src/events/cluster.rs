@@ -0,0 +1,318 @@
+use crate::db::{EventImage, NewEvent};
+use crate::geocode::distance_km;
+use chrono::{Datelike, Duration, NaiveDateTime, Weekday};
+use std::collections::HashMap;
+
+/// A longer pause between two photos ends the event, long enough to sleep over
+pub const MAX_GAP: Duration = Duration::hours(16);
+/// Moving farther than this from the last located photo starts a new event
+const MAX_DISTANCE_KM: f64 = 75.0;
+/// Smaller groups are left out
+const MIN_EVENT_IMAGES: usize = 5;
+
+/// Split images sorted by capture time into events
+pub fn cluster(images: &[EventImage]) -> Vec<NewEvent> {
+    let mut events = Vec::new();
+    let mut group: Vec<&EventImage> = Vec::new();
+    let mut last_location = None;
+
+    for image in images {
+        let location = image.latitude.zip(image.longitude);
+
+        let split = group.last().is_some_and(|previous| {
+            image.captured_at - previous.captured_at > MAX_GAP
+                || location
+                    .zip(last_location)
+                    .is_some_and(|(here, there)| distance_km(here, there) > MAX_DISTANCE_KM)
+        });
+
+        if split {
+            events.extend(finish(&group));
+            group.clear();
+            last_location = None;
+        }
+
+        group.push(image);
+        last_location = location.or(last_location);
+    }
+
+    events.extend(finish(&group));
+    events
+}
+
+fn finish(group: &[&EventImage]) -> Option<NewEvent> {
+    if group.len() < MIN_EVENT_IMAGES {
+        return None;
+    }
+
+    let (first, last) = (group.first()?, group.last()?);
+    let place = place_name(group);
+
+    Some(NewEvent {
+        id: None,
+        title: title(place.as_deref(), first.local_time, last.local_time),
+        place,
+        starts_at: first.captured_at,
+        ends_at: last.captured_at,
+        hashes: group.iter().map(|image| image.hash.clone()).collect(),
+    })
+}
+
+/// The city most photos were taken in, falling back to the region and then
+/// the country when the event covers several
+fn place_name(group: &[&EventImage]) -> Option<String> {
+    let located = group.iter().filter(|image| image.country.is_some()).count();
+
+    let levels: [fn(&EventImage) -> Option<&String>; 3] = [
+        |image| image.city.as_ref(),
+        |image| image.region.as_ref(),
+        |image| image.country.as_ref(),
+    ];
+
+    levels.into_iter().enumerate().find_map(|(i, level)| {
+        let (name, count) = most_common(group.iter().filter_map(|image| level(image)))?;
+        // The country is used whatever its share
+        (count * 2 >= located || i == levels.len() - 1).then(|| name.clone())
+    })
+}
+
+fn most_common<'a>(names: impl Iterator<Item = &'a String>) -> Option<(&'a String, usize)> {
+    let mut counts: HashMap<&String, usize> = HashMap::new();
+    for name in names {
+        *counts.entry(name).or_default() += 1;
+    }
+
+    // Ties are broken by name so recomputing gives the same title
+    counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+}
+
+/// e.g. "Weekend in Lisbon", "Trip to Portugal" or "Porto, 3 May 2024", dated
+/// by the local time the photos were taken at
+fn title(place: Option<&str>, starts_at: NaiveDateTime, ends_at: NaiveDateTime) -> String {
+    let (first, last) = (starts_at.date(), ends_at.date());
+    let days = (last - first).num_days() + 1;
+
+    let weekend = (2..=3).contains(&days)
+        && matches!(first.weekday(), Weekday::Fri | Weekday::Sat)
+        && matches!(last.weekday(), Weekday::Sat | Weekday::Sun);
+
+    match place {
+        Some(place) if weekend => format!("Weekend in {place}"),
+        Some(place) if days > 1 => format!("Trip to {place}"),
+        Some(place) => format!("{place}, {}", first.format("%-d %B %Y")),
+        None if days > 1 => format!("{} – {}", first.format("%-d %B"), last.format("%-d %B %Y")),
+        None => first.format("%-d %B %Y").to_string(),
+    }
+}
+
+/// Give each recomputed event the id of the existing event it shares the most
+/// images with, so links to events survive a regrouping. Each id goes to one event
+pub fn keep_ids(events: &mut [NewEvent], existing: &[(i64, Vec<String>)]) {
+    let mut pairs = Vec::new();
+    for (i, event) in events.iter().enumerate() {
+        for (id, hashes) in existing {
+            let shared = event.hashes.iter().filter(|h| hashes.contains(h)).count();
+            if shared > 0 {
+                pairs.push((shared, i, *id));
+            }
+        }
+    }
+
+    // Largest overlaps first, ties in event then id order
+    pairs.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+    let mut taken = Vec::new();
+    for (_, i, id) in pairs {
+        if events[i].id.is_none() && !taken.contains(&id) {
+            events[i].id = Some(id);
+            taken.push(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    const LISBON: (f64, f64) = (38.72, -9.14);
+    const PORTO: (f64, f64) = (41.15, -8.61);
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 5, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn image(hash: &str, time: NaiveDateTime, location: Option<(f64, f64)>) -> EventImage {
+        EventImage {
+            hash: hash.to_string(),
+            captured_at: Utc.from_utc_datetime(&time),
+            local_time: time,
+            latitude: location.map(|(lat, _)| lat),
+            longitude: location.map(|(_, lon)| lon),
+            country: None,
+            region: None,
+            city: None,
+        }
+    }
+
+    fn placed(city: &str, region: &str, country: &str) -> EventImage {
+        EventImage {
+            city: Some(city.to_string()),
+            region: Some(region.to_string()),
+            country: Some(country.to_string()),
+            ..image("h", at(3, 12), None)
+        }
+    }
+
+    fn event(hashes: &[&str]) -> NewEvent {
+        NewEvent {
+            id: None,
+            title: String::new(),
+            place: None,
+            starts_at: Utc.from_utc_datetime(&at(3, 12)),
+            ends_at: Utc.from_utc_datetime(&at(3, 12)),
+            hashes: hashes.iter().map(|h| h.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn cluster_splits_on_long_pauses() {
+        let images: Vec<_> = (0..5)
+            .map(|i| image(&format!("a{i}"), at(3, 8 + i), None))
+            .chain((0..5).map(|i| image(&format!("b{i}"), at(4, 14 + i), None)))
+            .collect();
+
+        let events = cluster(&images);
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].hashes, ["a0", "a1", "a2", "a3", "a4"]);
+        assert_eq!(events[1].hashes, ["b0", "b1", "b2", "b3", "b4"]);
+    }
+
+    #[test]
+    fn cluster_splits_when_moving_far() {
+        let images: Vec<_> = (0..10)
+            .map(|i| {
+                let location = if i < 5 { LISBON } else { PORTO };
+                image(&format!("h{i}"), at(3, 8 + i), Some(location))
+            })
+            .collect();
+
+        assert_eq!(cluster(&images).len(), 2);
+    }
+
+    #[test]
+    fn cluster_leaves_out_small_groups() {
+        let images: Vec<_> = (0..4)
+            .map(|i| image(&format!("h{i}"), at(3, 8 + i), None))
+            .collect();
+
+        assert!(cluster(&images).is_empty());
+    }
+
+    #[test]
+    fn cluster_titles_by_local_date() {
+        // Late evening in UTC is already the next day on the camera's clock
+        let images: Vec<_> = (0..5)
+            .map(|i| EventImage {
+                captured_at: Utc.from_utc_datetime(&at(3, 19 + i)),
+                ..image(&format!("h{i}"), at(3, 19 + i) + Duration::hours(9), None)
+            })
+            .collect();
+
+        assert_eq!(cluster(&images)[0].title, "4 May 2024");
+    }
+
+    #[test]
+    fn title_names_weekends_and_trips() {
+        // 3 May 2024 is a Friday
+        assert_eq!(
+            title(Some("Lisbon"), at(3, 18), at(5, 16)),
+            "Weekend in Lisbon"
+        );
+        assert_eq!(
+            title(Some("Portugal"), at(1, 9), at(4, 20)),
+            "Trip to Portugal"
+        );
+        assert_eq!(
+            title(Some("Porto"), at(3, 9), at(3, 20)),
+            "Porto, 3 May 2024"
+        );
+    }
+
+    #[test]
+    fn title_without_place_uses_dates() {
+        assert_eq!(title(None, at(1, 9), at(3, 20)), "1 May – 3 May 2024");
+        assert_eq!(title(None, at(3, 9), at(3, 20)), "3 May 2024");
+    }
+
+    #[test]
+    fn place_name_prefers_the_city_most_photos_share() {
+        let images = [
+            placed("Lisbon", "Lisbon", "Portugal"),
+            placed("Lisbon", "Lisbon", "Portugal"),
+            placed("Sintra", "Lisbon", "Portugal"),
+        ];
+        let group: Vec<_> = images.iter().collect();
+
+        assert_eq!(place_name(&group).as_deref(), Some("Lisbon"));
+    }
+
+    #[test]
+    fn place_name_falls_back_to_wider_areas() {
+        let images = [
+            placed("Lisbon", "Lisbon", "Portugal"),
+            placed("Sintra", "Lisbon", "Portugal"),
+            placed("Cascais", "Lisbon", "Portugal"),
+        ];
+        let group: Vec<_> = images.iter().collect();
+        assert_eq!(place_name(&group).as_deref(), Some("Lisbon"));
+
+        let images = [
+            placed("Lisbon", "Lisbon", "Portugal"),
+            placed("Porto", "Porto", "Portugal"),
+            placed("Faro", "Algarve", "Portugal"),
+        ];
+        let group: Vec<_> = images.iter().collect();
+        assert_eq!(place_name(&group).as_deref(), Some("Portugal"));
+    }
+
+    #[test]
+    fn place_name_is_none_without_locations() {
+        let images = [image("a", at(3, 9), None), image("b", at(3, 10), None)];
+        let group: Vec<_> = images.iter().collect();
+
+        assert_eq!(place_name(&group), None);
+    }
+
+    #[test]
+    fn keep_ids_follows_the_largest_overlap() {
+        let mut events = [event(&["a", "b"]), event(&["c", "d", "e"]), event(&["f"])];
+        let existing = [
+            (1, vec!["a".to_string(), "c".to_string()]),
+            (2, vec!["c".to_string(), "d".to_string()]),
+        ];
+
+        keep_ids(&mut events, &existing);
+
+        assert_eq!(events[0].id, Some(1));
+        assert_eq!(events[1].id, Some(2));
+        assert_eq!(events[2].id, None);
+    }
+
+    #[test]
+    fn keep_ids_gives_each_id_once() {
+        let mut events = [event(&["a", "b"]), event(&["c"])];
+        let existing = [(1, vec!["a".to_string(), "b".to_string(), "c".to_string()])];
+
+        keep_ids(&mut events, &existing);
+
+        assert_eq!(events[0].id, Some(1));
+        assert_eq!(events[1].id, None);
+    }
+}
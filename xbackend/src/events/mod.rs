@@ -0,0 +1,4 @@
+mod cluster;
+mod worker;
+
+pub use worker::run_event_grouping;
@@ -0,0 +1,65 @@
+use super::cluster::{MAX_GAP, cluster, keep_ids};
+use crate::db::{
+    get_changed_span, get_event_backlog, get_event_candidates, get_event_span, get_gap_span,
+    get_replaced_events, replace_events, set_event_cursor,
+};
+use crate::types::LibraryEvent;
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keep the automatic events up to date with the library, following the change
+/// journal so only the periods around changed images are recomputed
+pub async fn run_event_grouping(pool: PgPool, events: broadcast::Sender<LibraryEvent>) {
+    let mut receiver = events.subscribe();
+
+    loop {
+        if let Err(e) = group_pending(&pool).await {
+            eprintln!("Database error: {:?}", e);
+        }
+
+        tokio::select! {
+            _ = receiver.recv() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+async fn group_pending(pool: &PgPool) -> Result<(), sqlx::Error> {
+    for (owner, last_cursor) in get_event_backlog(pool).await? {
+        // One user's failure shouldn't hold up the others, they are retried next round
+        if let Err(e) = group_user(pool, &owner, last_cursor).await {
+            eprintln!("Database error: {:?}", e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn group_user(pool: &PgPool, owner: &str, last_cursor: i64) -> Result<(), sqlx::Error> {
+    // Only images that are gone changed, nothing to regroup
+    let Some((first, last)) = get_changed_span(pool, owner, last_cursor).await? else {
+        return set_event_cursor(&mut *pool.acquire().await?, owner, last_cursor).await;
+    };
+
+    // Regroup from pause to pause, events touching the span are redone whole
+    let (mut from, mut to) = get_gap_span(pool, owner, first, last, MAX_GAP).await?;
+    loop {
+        let span = get_event_span(pool, owner, from, to).await?;
+        if span == (from, to) {
+            break;
+        }
+        (from, to) = span;
+    }
+
+    let images = get_event_candidates(pool, owner, from, to).await?;
+    let mut events = cluster(&images);
+    keep_ids(
+        &mut events,
+        &get_replaced_events(pool, owner, from, to).await?,
+    );
+
+    replace_events(pool, owner, from, to, &events, last_cursor).await
+}
@@ -0,0 +1,167 @@
+use crate::types::Place;
+use std::collections::HashMap;
+use std::path::Path;
+use std::{fs, io};
+
+/// GeoNames city dumps, the most detailed one present is used
+const CITY_FILES: [&str; 4] = [
+    "cities500.txt",
+    "cities1000.txt",
+    "cities5000.txt",
+    "cities15000.txt",
+];
+const COUNTRY_FILE: &str = "countryInfo.txt";
+const REGION_FILE: &str = "admin1CodesASCII.txt";
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+/// Farther than this from any city, e.g. at sea, a location is left unresolved
+const MAX_CITY_DISTANCE_KM: f64 = 100.0;
+
+struct City {
+    name: String,
+    latitude: f64,
+    longitude: f64,
+    country_code: String,
+    region_code: String,
+}
+
+/// Cities indexed in a grid of one degree cells for nearest neighbour lookups
+pub struct Gazetteer {
+    cities: Vec<City>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    countries: HashMap<String, String>, // ISO code to name
+    regions: HashMap<String, String>,   // "US.CA" to name
+}
+
+impl Gazetteer {
+    /// Load the GeoNames files from `dir`, country and region names are optional
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let cities_path = CITY_FILES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.exists())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no GeoNames cities file"))?;
+
+        let mut gazetteer = Gazetteer {
+            cities: Vec::new(),
+            cells: HashMap::new(),
+            countries: HashMap::new(),
+            regions: HashMap::new(),
+        };
+
+        // geonameid, name, asciiname, alternatenames, latitude, longitude,
+        // feature class, feature code, country code, cc2, admin1 code, ...
+        for line in fs::read_to_string(cities_path)?.lines() {
+            let columns: Vec<&str> = line.split('\t').collect();
+            if columns.len() < 11 {
+                continue;
+            }
+
+            let (Ok(latitude), Ok(longitude)) = (columns[4].parse(), columns[5].parse()) else {
+                continue;
+            };
+
+            gazetteer
+                .cells
+                .entry(cell(latitude, longitude))
+                .or_default()
+                .push(gazetteer.cities.len());
+            gazetteer.cities.push(City {
+                name: columns[1].to_string(),
+                latitude,
+                longitude,
+                country_code: columns[8].to_string(),
+                region_code: columns[10].to_string(),
+            });
+        }
+
+        // ISO, ISO3, ISO-Numeric, fips, Country, ...
+        if let Ok(content) = fs::read_to_string(dir.join(COUNTRY_FILE)) {
+            for line in content.lines().filter(|l| !l.starts_with('#')) {
+                let columns: Vec<&str> = line.split('\t').collect();
+                if columns.len() > 4 {
+                    gazetteer
+                        .countries
+                        .insert(columns[0].to_string(), columns[4].to_string());
+                }
+            }
+        }
+
+        // code, name, ascii name, geonameid
+        if let Ok(content) = fs::read_to_string(dir.join(REGION_FILE)) {
+            for line in content.lines() {
+                let columns: Vec<&str> = line.split('\t').collect();
+                if columns.len() > 1 {
+                    gazetteer
+                        .regions
+                        .insert(columns[0].to_string(), columns[1].to_string());
+                }
+            }
+        }
+
+        Ok(gazetteer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.cities.len()
+    }
+
+    /// The place of the nearest city, if one is close enough
+    pub fn lookup(&self, latitude: f64, longitude: f64) -> Option<Place> {
+        let (row, column) = cell(latitude, longitude);
+
+        // Cells get narrower towards the poles, search wider there
+        let km_per_degree = EARTH_RADIUS_KM.to_radians();
+        let rows = (MAX_CITY_DISTANCE_KM / km_per_degree).ceil() as i32;
+        let columns = (MAX_CITY_DISTANCE_KM / (km_per_degree * latitude.to_radians().cos()))
+            .ceil()
+            .min(180.0) as i32;
+
+        let city = (row - rows..=row + rows)
+            .flat_map(|r| (column - columns..=column + columns).map(move |c| (r, wrap(c))))
+            .filter_map(|key| self.cells.get(&key))
+            .flatten()
+            .map(|&i| &self.cities[i])
+            .map(|city| {
+                (
+                    distance_km((latitude, longitude), (city.latitude, city.longitude)),
+                    city,
+                )
+            })
+            .filter(|(distance, _)| *distance <= MAX_CITY_DISTANCE_KM)
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, city)| city)?;
+
+        Some(Place {
+            country: self
+                .countries
+                .get(&city.country_code)
+                .cloned()
+                .unwrap_or_else(|| city.country_code.clone()),
+            region: self
+                .regions
+                .get(&format!("{}.{}", city.country_code, city.region_code))
+                .cloned(),
+            city: city.name.clone(),
+        })
+    }
+}
+
+fn cell(latitude: f64, longitude: f64) -> (i32, i32) {
+    (latitude.floor() as i32, wrap(longitude.floor() as i32))
+}
+
+/// Keep longitude cells in -180..180 so lookups work across the antimeridian
+fn wrap(column: i32) -> i32 {
+    (column + 180).rem_euclid(360) - 180
+}
+
+/// Great-circle distance using the haversine formula
+pub fn distance_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (to.1 - from.1).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
@@ -0,0 +1,5 @@
+mod gazetteer;
+mod worker;
+
+pub use gazetteer::distance_km;
+pub use worker::run_geocoder;
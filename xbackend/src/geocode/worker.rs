@@ -0,0 +1,97 @@
+use super::gazetteer::Gazetteer;
+use crate::db::{get_pending_locations, set_places};
+use crate::types::LibraryEvent;
+use sqlx::PgPool;
+use std::{collections::VecDeque, env, path::PathBuf, time::Duration};
+use tokio::sync::broadcast;
+
+const BATCH_SIZE: i64 = 500;
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// How many of its own journal cursors the geocoder remembers to ignore
+const OWN_CURSORS: usize = 16;
+
+/// Resolve the places of geotagged images, existing ones are backfilled on start.
+/// Disabled unless `GEONAMES_PATH` points at a directory with the GeoNames dumps.
+pub async fn run_geocoder(pool: PgPool, events: broadcast::Sender<LibraryEvent>) {
+    let Ok(path) = env::var("GEONAMES_PATH") else {
+        println!("GEONAMES_PATH not set, reverse geocoding disabled");
+        return;
+    };
+
+    let gazetteer =
+        match tokio::task::spawn_blocking(move || Gazetteer::load(&PathBuf::from(path))).await {
+            Ok(Ok(gazetteer)) => gazetteer,
+            Ok(Err(e)) => {
+                eprintln!("Failed to load gazetteer: {:?}", e);
+                return;
+            }
+            Err(e) => {
+                eprintln!("Gazetteer loader panicked: {:?}", e);
+                return;
+            }
+        };
+
+    println!("Gazetteer loaded with {} cities", gazetteer.len());
+
+    let mut receiver = events.subscribe();
+    let mut own_cursors = VecDeque::with_capacity(OWN_CURSORS);
+
+    loop {
+        match geocode_pending(&pool, &gazetteer, &mut own_cursors).await {
+            // There may be more waiting
+            Ok(count) if count as i64 == BATCH_SIZE => continue,
+            Ok(_) => {}
+            Err(e) => eprintln!("Database error: {:?}", e),
+        }
+
+        // New uploads and location edits show up as library changes,
+        // polling covers anything missed
+        tokio::select! {
+            _ = next_change(&mut receiver, &own_cursors) => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// Wait for a library change that wasn't written by the geocoder itself
+async fn next_change(
+    receiver: &mut broadcast::Receiver<LibraryEvent>,
+    own_cursors: &VecDeque<i64>,
+) {
+    loop {
+        match receiver.recv().await {
+            Ok(LibraryEvent::Changed { change, .. }) if own_cursors.contains(&change.cursor) => {}
+            _ => return,
+        }
+    }
+}
+
+async fn geocode_pending(
+    pool: &PgPool,
+    gazetteer: &Gazetteer,
+    own_cursors: &mut VecDeque<i64>,
+) -> Result<usize, sqlx::Error> {
+    let pending = get_pending_locations(pool, BATCH_SIZE).await?;
+    let count = pending.len();
+
+    if count == 0 {
+        return Ok(0);
+    }
+
+    let resolved: Vec<_> = pending
+        .into_iter()
+        .map(|location| {
+            let place = gazetteer.lookup(location.latitude, location.longitude);
+            (location, place)
+        })
+        .collect();
+
+    if let Some(cursor) = set_places(pool, &resolved).await? {
+        if own_cursors.len() == OWN_CURSORS {
+            own_cursors.pop_front();
+        }
+        own_cursors.push_back(cursor);
+    }
+
+    Ok(count)
+}
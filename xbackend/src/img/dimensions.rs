@@ -0,0 +1,44 @@
+/// Read the pixel dimensions from a JPEG, PNG or GIF header
+pub fn image_dimensions(body: &[u8]) -> Option<(u32, u32)> {
+    if body.starts_with(b"\x89PNG\r\n\x1a\n") {
+        // IHDR is always the first chunk
+        let width = u32::from_be_bytes(body.get(16..20)?.try_into().ok()?);
+        let height = u32::from_be_bytes(body.get(20..24)?.try_into().ok()?);
+        return Some((width, height));
+    }
+
+    if body.starts_with(b"GIF87a") || body.starts_with(b"GIF89a") {
+        let width = u16::from_le_bytes(body.get(6..8)?.try_into().ok()?);
+        let height = u16::from_le_bytes(body.get(8..10)?.try_into().ok()?);
+        return Some((width.into(), height.into()));
+    }
+
+    if body.starts_with(&[0xFF, 0xD8]) {
+        return jpeg_dimensions(body);
+    }
+
+    None
+}
+
+fn jpeg_dimensions(body: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    while pos + 4 <= body.len() {
+        if body[pos] != 0xFF {
+            return None;
+        }
+
+        let marker = body[pos + 1];
+        let length = u16::from_be_bytes([body[pos + 2], body[pos + 3]]) as usize;
+
+        // SOF0 to SOF15, except DHT, JPG and DAC which share the range
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            let height = u16::from_be_bytes(body.get(pos + 5..pos + 7)?.try_into().ok()?);
+            let width = u16::from_be_bytes(body.get(pos + 7..pos + 9)?.try_into().ok()?);
+            return Some((width.into(), height.into()));
+        }
+
+        pos += 2 + length;
+    }
+
+    None
+}
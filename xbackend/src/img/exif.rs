@@ -1,3 +1,4 @@
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
 use std::io::Cursor;
 
 fn extract_gps_coordinate_numeric(
@@ -6,49 +7,125 @@ fn extract_gps_coordinate_numeric(
     ref_tag: exif::Tag,
     negative_ref: char,
 ) -> Option<f64> {
-    if let Some(coord) = exif.get_field(coord_tag, exif::In::PRIMARY) {
-        if let exif::Value::Rational(ref vals) = coord.value {
-            if vals.len() >= 3 {
-                let degrees = vals[0].num as f64 / vals[0].denom as f64;
-                let minutes = vals[1].num as f64 / vals[1].denom as f64;
-                let seconds = vals[2].num as f64 / vals[2].denom as f64;
-                let mut decimal = degrees + minutes / 60.0 + seconds / 3600.0;
-
-                let coord_ref = exif
-                    .get_field(ref_tag, exif::In::PRIMARY)
-                    .and_then(|f| f.value.display_as(f.tag).to_string().chars().next())
-                    .unwrap_or(' ');
-
-                if coord_ref == negative_ref {
-                    decimal = -decimal;
-                }
-
-                return Some(decimal);
-            }
+    if let Some(coord) = exif.get_field(coord_tag, exif::In::PRIMARY)
+        && let exif::Value::Rational(ref vals) = coord.value
+        && vals.len() >= 3
+    {
+        let degrees = vals[0].num as f64 / vals[0].denom as f64;
+        let minutes = vals[1].num as f64 / vals[1].denom as f64;
+        let seconds = vals[2].num as f64 / vals[2].denom as f64;
+        let mut decimal = degrees + minutes / 60.0 + seconds / 3600.0;
+
+        let coord_ref = exif
+            .get_field(ref_tag, exif::In::PRIMARY)
+            .and_then(|f| f.value.display_as(f.tag).to_string().chars().next())
+            .unwrap_or(' ');
+
+        if coord_ref == negative_ref {
+            decimal = -decimal;
         }
+
+        return Some(decimal);
     }
     None
 }
 
-pub fn extract_gps_numeric(body: &[u8]) -> (Option<f64>, Option<f64>) {
-    let cursor = Cursor::new(body);
-    let exif_reader = exif::Reader::new();
-    match exif_reader.read_from_container(&mut cursor.clone()) {
-        Ok(exif) => {
-            let latitude = extract_gps_coordinate_numeric(
-                &exif,
-                exif::Tag::GPSLatitude,
-                exif::Tag::GPSLatitudeRef,
-                'S',
-            );
-            let longitude = extract_gps_coordinate_numeric(
-                &exif,
-                exif::Tag::GPSLongitude,
-                exif::Tag::GPSLongitudeRef,
-                'W',
-            );
-            (latitude, longitude)
+pub fn read_exif(body: &[u8]) -> Option<exif::Exif> {
+    let mut cursor = Cursor::new(body);
+    exif::Reader::new().read_from_container(&mut cursor).ok()
+}
+
+pub fn extract_gps_numeric(exif: &exif::Exif) -> (Option<f64>, Option<f64>) {
+    let latitude = extract_gps_coordinate_numeric(
+        exif,
+        exif::Tag::GPSLatitude,
+        exif::Tag::GPSLatitudeRef,
+        'S',
+    );
+    let longitude = extract_gps_coordinate_numeric(
+        exif,
+        exif::Tag::GPSLongitude,
+        exif::Tag::GPSLongitudeRef,
+        'W',
+    );
+    (latitude, longitude)
+}
+
+/// Capture time from DateTimeOriginal, converted to UTC when an offset is
+/// recorded, and that offset in minutes east of UTC
+pub fn extract_taken_at(exif: &exif::Exif) -> Option<(DateTime<Utc>, Option<i16>)> {
+    let (tag, offset_tag) = [
+        (exif::Tag::DateTimeOriginal, exif::Tag::OffsetTimeOriginal),
+        (exif::Tag::DateTime, exif::Tag::OffsetTime),
+    ]
+    .into_iter()
+    .find(|(tag, _)| exif.get_field(*tag, exif::In::PRIMARY).is_some())?;
+
+    let mut datetime = match exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Ascii(ref vals) if !vals.is_empty() => {
+            exif::DateTime::from_ascii(&vals[0]).ok()?
         }
-        Err(_) => (None, None),
+        _ => return None,
+    };
+
+    if let Some(field) = exif.get_field(offset_tag, exif::In::PRIMARY)
+        && let exif::Value::Ascii(ref vals) = field.value
+        && !vals.is_empty()
+    {
+        // An unparsable offset leaves the time as local time
+        let _ = datetime.parse_offset(&vals[0]);
     }
+
+    let naive = NaiveDate::from_ymd_opt(
+        datetime.year.into(),
+        datetime.month.into(),
+        datetime.day.into(),
+    )?
+    .and_hms_opt(
+        datetime.hour.into(),
+        datetime.minute.into(),
+        datetime.second.into(),
+    )?;
+
+    // Without an offset the local time is stored as if it were UTC
+    let offset_minutes = datetime.offset.unwrap_or(0);
+    Some((
+        Utc.from_utc_datetime(&naive) - Duration::minutes(offset_minutes.into()),
+        datetime.offset,
+    ))
+}
+
+/// Pixel dimensions as recorded by the camera, before applying the orientation
+pub fn extract_dimensions(exif: &exif::Exif) -> Option<(u32, u32)> {
+    let read = |tags: [exif::Tag; 2]| {
+        tags.into_iter().find_map(|tag| {
+            exif.get_field(tag, exif::In::PRIMARY)
+                .and_then(|f| f.value.get_uint(0))
+        })
+    };
+
+    let width = read([exif::Tag::PixelXDimension, exif::Tag::ImageWidth])?;
+    let height = read([exif::Tag::PixelYDimension, exif::Tag::ImageLength])?;
+
+    Some((width, height))
+}
+
+/// Orientation values 5 to 8 mean the image is displayed rotated by 90 degrees
+pub fn is_rotated(exif: &exif::Exif) -> bool {
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0))
+        .is_some_and(|o| (5..=8).contains(&o))
+}
+
+/// Camera model as written by the manufacturer, e.g. "Pixel 7" or "iPhone 13"
+pub fn extract_camera_model(exif: &exif::Exif) -> Option<String> {
+    let field = exif.get_field(exif::Tag::Model, exif::In::PRIMARY)?;
+    let exif::Value::Ascii(ref vals) = field.value else {
+        return None;
+    };
+
+    let model = String::from_utf8_lossy(vals.first()?);
+    let model = model.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+
+    (!model.is_empty()).then(|| model.chars().take(255).collect())
 }
@@ -2,3 +2,17 @@ pub fn compute_hash(body: &[u8]) -> String {
     let hash = blake3::hash(body);
     hash.to_hex().to_string()
 }
+
+/// Incremental variant of `compute_hash` for content that arrives in chunks
+#[derive(Default)]
+pub struct ContentHasher(blake3::Hasher);
+
+impl ContentHasher {
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.0.update(chunk);
+    }
+
+    pub fn finalize(&self) -> String {
+        self.0.finalize().to_hex().to_string()
+    }
+}
@@ -0,0 +1,102 @@
+use super::xmp::{find, xmp_packet};
+
+/// Keywords from the XMP `dc:subject` bag and the IPTC keywords dataset
+pub fn extract_keywords(body: &[u8]) -> Vec<String> {
+    let mut keywords = xmp_keywords(body);
+    keywords.extend(iptc_keywords(body));
+    keywords
+}
+
+fn xmp_keywords(body: &[u8]) -> Vec<String> {
+    let Some(packet) = xmp_packet(body) else {
+        return Vec::new();
+    };
+
+    let Some(start) = packet.find("<dc:subject") else {
+        return Vec::new();
+    };
+    let end = packet[start..]
+        .find("</dc:subject>")
+        .map_or(packet.len(), |end| start + end);
+
+    let mut keywords = Vec::new();
+    let mut rest = &packet[start..end];
+    while let Some(open) = rest.find("<rdf:li") {
+        rest = &rest[open..];
+        let Some(content_start) = rest.find('>') else {
+            break;
+        };
+
+        // An empty `<rdf:li/>` has no closing tag of its own
+        if rest[..content_start].ends_with('/') {
+            rest = &rest[content_start + 1..];
+            continue;
+        }
+
+        let Some(content_end) = rest.find("</rdf:li>") else {
+            break;
+        };
+
+        if content_start < content_end {
+            keywords.push(unescape_xml(&rest[content_start + 1..content_end]));
+        }
+        rest = &rest[content_end..];
+    }
+
+    keywords
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// IPTC-NAA record 2 dataset 25, stored in the Photoshop image resource 0x0404
+fn iptc_keywords(body: &[u8]) -> Vec<String> {
+    let Some(resource) = find(body, b"8BIM\x04\x04") else {
+        return Vec::new();
+    };
+    let mut pos = resource + 6;
+
+    // Pascal string name, padded to an even length including the length byte
+    let Some(&name_length) = body.get(pos) else {
+        return Vec::new();
+    };
+    pos += (name_length as usize + 2) & !1;
+
+    let Some(size) = body.get(pos..pos + 4) else {
+        return Vec::new();
+    };
+    let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+    pos += 4;
+
+    let Some(data) = body.get(pos..pos.saturating_add(size)) else {
+        return Vec::new();
+    };
+
+    let mut keywords = Vec::new();
+    let mut pos = 0;
+    while pos + 5 <= data.len() && data[pos] == 0x1C {
+        let (record, dataset) = (data[pos + 1], data[pos + 2]);
+        let length = u16::from_be_bytes([data[pos + 3], data[pos + 4]]) as usize;
+        pos += 5;
+
+        // Extended lengths are only used for large binary datasets
+        if length & 0x8000 != 0 {
+            break;
+        }
+
+        let Some(value) = data.get(pos..pos + length) else {
+            break;
+        };
+        if record == 2 && dataset == 25 {
+            keywords.push(String::from_utf8_lossy(value).into_owned());
+        }
+        pos += length;
+    }
+
+    keywords
+}
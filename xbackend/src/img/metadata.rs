@@ -0,0 +1,53 @@
+use super::dimensions::image_dimensions;
+use super::exif::{
+    extract_camera_model, extract_dimensions, extract_gps_numeric, extract_taken_at, is_rotated,
+    read_exif,
+};
+use super::keywords::extract_keywords;
+use super::xmp::extract_rating;
+use chrono::{DateTime, Utc};
+
+/// Everything the upload pipeline learns from the file itself
+#[derive(Debug, Default)]
+pub struct ImageMetadata {
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub taken_at: Option<DateTime<Utc>>,
+    pub taken_at_offset: Option<i16>, // minutes east of UTC the camera clock was set to
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub camera_model: Option<String>,
+    pub keywords: Vec<String>,
+    pub rating: Option<i16>,
+}
+
+/// Metadata sits at the start of the file in the formats we read, only this
+/// much of a large upload is kept in memory for it
+pub const METADATA_HEAD_SIZE: usize = 8 * 1024 * 1024;
+
+pub fn extract_metadata(body: &[u8]) -> ImageMetadata {
+    let exif = read_exif(body);
+
+    let (latitude, longitude) = exif.as_ref().map_or((None, None), extract_gps_numeric);
+
+    // The header is authoritative, EXIF is only a fallback for formats we don't parse
+    let dimensions = image_dimensions(body).or_else(|| exif.as_ref().and_then(extract_dimensions));
+    let dimensions = match dimensions {
+        Some((width, height)) if exif.as_ref().is_some_and(is_rotated) => Some((height, width)),
+        other => other,
+    };
+
+    let taken_at = exif.as_ref().and_then(extract_taken_at);
+
+    ImageMetadata {
+        latitude,
+        longitude,
+        taken_at: taken_at.map(|(t, _)| t),
+        taken_at_offset: taken_at.and_then(|(_, offset)| offset),
+        width: dimensions.and_then(|(w, _)| i32::try_from(w).ok()),
+        height: dimensions.and_then(|(_, h)| i32::try_from(h).ok()),
+        camera_model: exif.as_ref().and_then(extract_camera_model),
+        keywords: extract_keywords(body),
+        rating: extract_rating(body),
+    }
+}
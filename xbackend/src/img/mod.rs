@@ -1,5 +1,13 @@
+mod dimensions;
 mod exif;
 mod hash;
+mod keywords;
+mod metadata;
+mod preview;
+mod sizes;
+mod xmp;
 
-pub use exif::extract_gps_numeric;
-pub use hash::compute_hash;
+pub use hash::{ContentHasher, compute_hash};
+pub use metadata::{METADATA_HEAD_SIZE, extract_metadata};
+pub use preview::render_preview;
+pub use sizes::backfill_sizes;
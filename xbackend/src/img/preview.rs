@@ -0,0 +1,31 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use std::io::Cursor;
+
+const PREVIEW_QUALITY: u8 = 80;
+
+/// Downscaled JPEG copy of an image, upright and without its metadata.
+/// `None` for formats that can't be decoded, like videos
+pub fn render_preview(body: &[u8], max_side: u32) -> Option<Vec<u8>> {
+    let mut decoder = ImageReader::new(Cursor::new(body))
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    let orientation = decoder.orientation().ok()?;
+
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+    image.apply_orientation(orientation);
+
+    // Never upscale
+    if image.width() > max_side || image.height() > max_side {
+        image = image.thumbnail(max_side, max_side);
+    }
+
+    let mut preview = Vec::new();
+    JpegEncoder::new_with_quality(&mut preview, PREVIEW_QUALITY)
+        .encode_image(&image.to_rgb8())
+        .ok()?;
+
+    Some(preview)
+}
@@ -0,0 +1,46 @@
+use crate::db::{get_unsized_images, set_image_size};
+use sqlx::PgPool;
+use std::{env, path::PathBuf};
+
+const BATCH_SIZE: i64 = 500;
+
+/// Record the file size of images stored before sizes were, so they count
+/// towards the quota. Runs once on start
+pub async fn backfill_sizes(pool: PgPool) {
+    let Ok(storage_path) = env::var("IMAGE_STORAGE_PATH") else {
+        return;
+    };
+
+    loop {
+        let images = match get_unsized_images(&pool, BATCH_SIZE).await {
+            Ok(images) if images.is_empty() => return,
+            Ok(images) => images,
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                return;
+            }
+        };
+
+        for (hash, extension) in images {
+            let file_path = PathBuf::from(&storage_path).join(format!("{}.{}", hash, extension));
+
+            // A missing file takes no space, and isn't looked for again
+            let size = match tokio::fs::metadata(&file_path).await {
+                Ok(metadata) => metadata.len() as i64,
+                Err(e) => {
+                    eprintln!(
+                        "Warning: Could not read file {}: {:?}",
+                        file_path.display(),
+                        e
+                    );
+                    0
+                }
+            };
+
+            if let Err(e) = set_image_size(&pool, &hash, size).await {
+                eprintln!("Database error: {:?}", e);
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+/// The XMP packet as text, if the file embeds one
+pub fn xmp_packet(body: &[u8]) -> Option<&str> {
+    let start = find(body, b"<x:xmpmeta")?;
+    let end = start + find(&body[start..], b"</x:xmpmeta>")?;
+    std::str::from_utf8(&body[start..end]).ok()
+}
+
+/// Star rating from `xmp:Rating`, zero (unrated) and -1 (rejected) are ignored
+pub fn extract_rating(body: &[u8]) -> Option<i16> {
+    let packet = xmp_packet(body)?;
+
+    // Written either as an attribute or as an element
+    let value = if let Some(start) = packet.find("xmp:Rating=\"") {
+        let value = &packet[start + 12..];
+        &value[..value.find('"')?]
+    } else {
+        let start = packet.find("<xmp:Rating>")?;
+        let value = &packet[start + 12..];
+        &value[..value.find('<')?]
+    };
+
+    // Some writers store fractional ratings
+    let rating = value.trim().parse::<f64>().ok()?.round();
+    (1.0..=5.0).contains(&rating).then_some(rating as i16)
+}
+
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
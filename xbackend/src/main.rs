@@ -1,13 +1,24 @@
+mod admin;
 mod db;
+mod events;
+mod geocode;
 mod img;
 mod routes;
+mod secret;
 mod types;
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
 
+    let args: Vec<String> = std::env::args().collect();
+
     let pool = db::init().await;
 
+    // `backend admin ...` manages users instead of starting the server
+    if args.get(1).map(String::as_str) == Some("admin") {
+        std::process::exit(admin::run(&pool, &args[2..]).await);
+    }
+
     routes::init(pool).await;
 }
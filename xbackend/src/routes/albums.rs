@@ -0,0 +1,361 @@
+use crate::db::{
+    add_album_images, create_album, delete_album, get_album, get_album_images, get_album_members,
+    get_albums, remove_album_images, remove_album_member, reorder_album, set_album_member,
+    update_album,
+};
+use crate::routes::auth::Claims;
+use crate::routes::image::{ImageMetadataResponse, deserialize_nullable};
+use crate::types::{Album, AlbumMember, AlbumRole};
+use axum::{Extension, Json, extract::Path, extract::Query, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+const DEFAULT_ALBUM_IMAGES_LIMIT: i64 = 100;
+const MAX_ALBUM_IMAGES_LIMIT: i64 = 500;
+const MAX_ALBUM_HASHES: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct CreateAlbumRequest {
+    pub name: String,
+}
+
+fn valid_album_name(name: &str) -> bool {
+    !name.trim().is_empty() && name.len() <= 255
+}
+
+pub async fn create_album_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Json(request): Json<CreateAlbumRequest>,
+) -> Result<(StatusCode, Json<Album>), StatusCode> {
+    if !valid_album_name(&request.name) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let album = create_album(&pool, &claims.sub, request.name.trim())
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok((StatusCode::CREATED, Json(album)))
+}
+
+#[derive(Serialize)]
+pub struct GetAlbumsResponse {
+    pub albums: Vec<Album>,
+}
+
+pub async fn get_albums_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<GetAlbumsResponse>, StatusCode> {
+    let albums = get_albums(&pool, &claims.sub).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(GetAlbumsResponse { albums }))
+}
+
+#[derive(Deserialize)]
+pub struct GetAlbumQuery {
+    #[serde(default)]
+    pub offset: i64,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct GetAlbumResponse {
+    #[serde(flatten)]
+    pub album: Album,
+    pub images: Vec<ImageMetadataResponse>,
+}
+
+pub async fn get_album_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+    Query(query): Query<GetAlbumQuery>,
+) -> Result<Json<GetAlbumResponse>, StatusCode> {
+    let album = get_album(&pool, id, &claims.sub)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_ALBUM_IMAGES_LIMIT)
+        .clamp(1, MAX_ALBUM_IMAGES_LIMIT);
+
+    let images = get_album_images(&pool, id, query.offset.max(0), limit)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(GetAlbumResponse {
+        album,
+        images: images
+            .into_iter()
+            .map(ImageMetadataResponse::from)
+            .collect(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateAlbumRequest {
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub cover_hash: Option<Option<String>>,
+}
+
+pub async fn update_album_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+    Json(request): Json<UpdateAlbumRequest>,
+) -> Result<Json<Album>, StatusCode> {
+    if request
+        .name
+        .as_deref()
+        .is_some_and(|n| !valid_album_name(n))
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    check_album(&pool, id, &claims.sub, &[], AlbumRole::is_owner).await?;
+
+    let updated = update_album(
+        &pool,
+        id,
+        &claims.sub,
+        request.name.as_deref().map(str::trim),
+        request.cover_hash.as_ref().map(|c| c.as_deref()),
+    )
+    .await
+    .map_err(|e| match e {
+        // The cover is not part of the album
+        sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() => {
+            StatusCode::BAD_REQUEST
+        }
+        e => {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+
+    if !updated {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let album = get_album(&pool, id, &claims.sub)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(album))
+}
+
+pub async fn delete_album_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+    check_album(&pool, id, &claims.sub, &[], AlbumRole::is_owner).await?;
+
+    let deleted = delete_album(&pool, id, &claims.sub).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if deleted {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AlbumImagesRequest {
+    pub hashes: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct AlbumImagesResponse {
+    pub hashes: Vec<String>, // the hashes that were actually added or removed
+}
+
+pub async fn add_album_images_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+    Json(request): Json<AlbumImagesRequest>,
+) -> Result<Json<AlbumImagesResponse>, StatusCode> {
+    check_album(&pool, id, &claims.sub, &request.hashes, AlbumRole::can_add).await?;
+
+    let hashes = add_album_images(&pool, id, &claims.sub, &request.hashes)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(AlbumImagesResponse { hashes }))
+}
+
+pub async fn remove_album_images_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+    Json(request): Json<AlbumImagesRequest>,
+) -> Result<Json<AlbumImagesResponse>, StatusCode> {
+    let album = check_album(&pool, id, &claims.sub, &request.hashes, AlbumRole::can_add).await?;
+
+    // Contributors can only take out the images they added
+    let image_owner = (album.role != AlbumRole::Owner).then_some(claims.sub.as_str());
+
+    let hashes = remove_album_images(&pool, id, image_owner, &request.hashes)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(AlbumImagesResponse { hashes }))
+}
+
+/// Put the listed images first, in the given order
+pub async fn reorder_album_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+    Json(request): Json<AlbumImagesRequest>,
+) -> Result<StatusCode, StatusCode> {
+    check_album(&pool, id, &claims.sub, &request.hashes, AlbumRole::is_owner).await?;
+
+    reorder_album(&pool, id, &request.hashes)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize)]
+pub struct GetAlbumMembersResponse {
+    pub members: Vec<AlbumMember>,
+}
+
+pub async fn get_album_members_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+) -> Result<Json<GetAlbumMembersResponse>, StatusCode> {
+    check_album(&pool, id, &claims.sub, &[], |_| true).await?;
+
+    let members = get_album_members(&pool, id).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(GetAlbumMembersResponse { members }))
+}
+
+#[derive(Deserialize)]
+pub struct SetAlbumMemberRequest {
+    pub role: AlbumRole,
+}
+
+/// Share the album with a user, or change the role they have
+pub async fn set_album_member_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path((id, username)): Path<(i64, String)>,
+    Json(request): Json<SetAlbumMemberRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let album = check_album(&pool, id, &claims.sub, &[], AlbumRole::is_owner).await?;
+
+    if request.role == AlbumRole::Owner || username == album.owner {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    set_album_member(&pool, id, &username, request.role)
+        .await
+        .map_err(|e| match e {
+            // No such user
+            sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() => {
+                StatusCode::NOT_FOUND
+            }
+            e => {
+                eprintln!("Database error: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Stop sharing the album with a user, members can also remove themselves
+pub async fn remove_album_member_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path((id, username)): Path<(i64, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let album = check_album(&pool, id, &claims.sub, &[], |_| true).await?;
+
+    if album.role != AlbumRole::Owner && username != claims.sub {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let removed = remove_album_member(&pool, id, &username)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if removed {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+/// The album as seen by the user, 404 when it is not shared with them and 403
+/// when their role does not allow the operation
+async fn check_album(
+    pool: &PgPool,
+    id: i64,
+    username: &str,
+    hashes: &[String],
+    allowed: impl Fn(&AlbumRole) -> bool,
+) -> Result<Album, StatusCode> {
+    if hashes.len() > MAX_ALBUM_HASHES {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let album = get_album(pool, id, username)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if !allowed(&album.role) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(album)
+}
@@ -1,57 +1,188 @@
-use axum::{Json, extract::State, http::StatusCode};
-use axum::{extract::Request, http::HeaderMap, middleware::Next, response::Response};
+use axum::{Extension, Json, extract::State, http::StatusCode};
+use axum::{extract::Request, http::HeaderMap, http::header, middleware::Next, response::Response};
+use chrono::{Duration, Utc};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use std::env;
 
-use crate::db::{UserError, validate_user};
+use crate::db::{
+    Rotation, UserError, authenticate_api_token, check_session, create_session, rotate_session,
+    validate_user,
+};
+use crate::secret::{generate_token, hash_token};
 use crate::types::UserCredentials;
 
+/// Access tokens can't be revoked, so they are kept short
+const ACCESS_TOKEN_LIFETIME: Duration = Duration::minutes(15);
+/// A session ends when it hasn't been refreshed for this long
+const SESSION_LIFETIME: Duration = Duration::days(30);
+/// A replaced refresh token still works this long, for clients retrying a refresh
+const REFRESH_RETRY_WINDOW: Duration = Duration::seconds(30);
+const MAX_DEVICE_NAME_LENGTH: usize = 255;
+/// Tells API tokens apart from JWTs
+pub const API_TOKEN_PREFIX: &str = "kc_";
+/// `aud` of access tokens, other JWTs signed with the same secret are refused
+const ACCESS_AUDIENCE: &str = "access";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub sub: String,
+    pub sid: Option<i64>, // the session of a JWT, `None` for API tokens
+    #[serde(skip)]
+    pub device_id: Option<i64>, // the device an API token acts for
+    pub aud: String,
     pub exp: usize,
 }
 
+impl Claims {
+    /// API tokens have no session and are refused
+    pub fn session_id(&self) -> Result<i64, StatusCode> {
+        self.sid.ok_or(StatusCode::FORBIDDEN)
+    }
+
+    /// API tokens only act for their own device
+    pub fn allows_device(&self, device_id: i64) -> bool {
+        self.device_id.is_none_or(|id| id == device_id)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+    pub device_name: Option<String>, // shown in the session list, defaults to the user agent
+}
+
 #[derive(Serialize)]
 pub struct LoginResponse {
     token: String,
+    refresh_token: String, // single use, every refresh returns a new one
+    expires_in: i64,       // seconds until `token` expires
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+pub fn jwt_secret() -> String {
+    env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string())
+}
+
+/// Tokens for a session whose refresh token was just set to `refresh_token`
+fn token_response(
+    username: String,
+    session_id: i64,
+    refresh_token: String,
+) -> Result<Json<LoginResponse>, StatusCode> {
+    let claims = Claims {
+        sub: username,
+        sid: Some(session_id),
+        device_id: None,
+        aud: ACCESS_AUDIENCE.to_string(),
+        exp: (Utc::now() + ACCESS_TOKEN_LIFETIME).timestamp() as usize,
+    };
+
+    match encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(jwt_secret().as_bytes()),
+    ) {
+        Ok(token) => Ok(Json(LoginResponse {
+            token,
+            refresh_token,
+            expires_in: ACCESS_TOKEN_LIFETIME.num_seconds(),
+        })),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
 }
 
 pub async fn login(
     State(pool): State<PgPool>,
-    Json(credentials): Json<UserCredentials>,
+    headers: HeaderMap,
+    Json(request): Json<LoginRequest>,
 ) -> Result<Json<LoginResponse>, StatusCode> {
+    let credentials = UserCredentials {
+        username: request.username,
+        password: request.password,
+    };
+
     // Validate the user credentials
-    match validate_user(&pool, &credentials).await {
-        Ok(user) => {
-            // Create JWT claims
-            let claims = Claims {
-                sub: user.username,
-                exp: (chrono::Utc::now() + chrono::Duration::hours(24)).timestamp() as usize,
-            };
-
-            // Get JWT secret from environment
-            let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string());
-
-            // Encode the JWT token
-            match encode(
-                &Header::default(),
-                &claims,
-                &EncodingKey::from_secret(secret.as_bytes()),
-            ) {
-                Ok(token) => Ok(Json(LoginResponse { token })),
-                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-            }
+    let user = match validate_user(&pool, &credentials).await {
+        Ok(user) => user,
+        Err(UserError::InvalidCredentials) => return Err(StatusCode::UNAUTHORIZED),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let device_name = request
+        .device_name
+        .or_else(|| {
+            headers
+                .get(header::USER_AGENT)
+                .and_then(|h| h.to_str().ok())
+                .map(|agent| agent.chars().take(MAX_DEVICE_NAME_LENGTH).collect())
+        })
+        .filter(|name| !name.is_empty());
+
+    if device_name
+        .as_ref()
+        .is_some_and(|name| name.chars().count() > MAX_DEVICE_NAME_LENGTH)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let refresh_token = generate_token();
+
+    let session_id = create_session(
+        &pool,
+        &user.username,
+        device_name.as_deref(),
+        &hash_token(&refresh_token),
+        Utc::now() + SESSION_LIFETIME,
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    token_response(user.username, session_id, refresh_token)
+}
+
+/// Trade a refresh token for a new access token and refresh token
+pub async fn refresh(
+    State(pool): State<PgPool>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<Json<LoginResponse>, StatusCode> {
+    let refresh_token = generate_token();
+
+    let rotation = rotate_session(
+        &pool,
+        &hash_token(&request.refresh_token),
+        &hash_token(&refresh_token),
+        Utc::now() + SESSION_LIFETIME,
+        REFRESH_RETRY_WINDOW,
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    match rotation {
+        Rotation::Rotated { id, username } => token_response(username, id, refresh_token),
+        Rotation::Reused => {
+            eprintln!("Refresh token reused, session ended");
+            Err(StatusCode::UNAUTHORIZED)
         }
-        Err(UserError::InvalidCredentials) => Err(StatusCode::UNAUTHORIZED),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        Rotation::Invalid => Err(StatusCode::UNAUTHORIZED),
     }
 }
 
-/// Middleware function that validates JWT tokens
+/// Middleware function that validates JWT tokens and their sessions, or API tokens
 pub async fn auth_middleware(
+    State(pool): State<PgPool>,
     headers: HeaderMap,
     mut req: Request,
     next: Next,
@@ -70,20 +201,66 @@ pub async fn auth_middleware(
     // Extract the token
     let token = auth_header.trim_start_matches("Bearer ");
 
-    // Get JWT secret from environment
-    let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string());
+    if token.starts_with(API_TOKEN_PREFIX) {
+        let (owner, device_id) = authenticate_api_token(&pool, &hash_token(token))
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        req.extensions_mut().insert(Claims {
+            sub: owner,
+            sid: None,
+            device_id: Some(device_id),
+            aud: ACCESS_AUDIENCE.to_string(),
+            exp: 0, // API tokens last until revoked
+        });
+
+        return Ok(next.run(req).await);
+    }
 
     // Decode and validate the token
+    let mut validation = Validation::default();
+    validation.set_audience(&[ACCESS_AUDIENCE]);
     let token_data = decode::<Claims>(
         token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
+        &DecodingKey::from_secret(jwt_secret().as_bytes()),
+        &validation,
     )
     .map_err(|_| StatusCode::UNAUTHORIZED)?;
 
+    // Tokens from before sessions have none
+    let session_id = token_data.claims.sid.ok_or(StatusCode::UNAUTHORIZED)?;
+
+    // The session may have been logged out since the token was issued
+    let open = check_session(&pool, session_id, &token_data.claims.sub)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if !open {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
     // Insert the claims into request extensions so handlers can access them
     req.extensions_mut().insert(token_data.claims);
 
     // Continue to the next middleware/handler
     Ok(next.run(req).await)
 }
+
+/// Lets logins through and refuses API tokens, which are limited to the sync
+/// routes. Runs after `auth_middleware`
+pub async fn session_middleware(
+    Extension(claims): Extension<Claims>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    claims.session_id()?;
+
+    Ok(next.run(req).await)
+}
@@ -0,0 +1,132 @@
+use crate::db::{apply_batch, get_album};
+use crate::routes::auth::Claims;
+use crate::routes::image::UpdateImageRequest;
+use crate::types::{BatchOperation, ImageUpdate, Location};
+use axum::{Extension, Json, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::{collections::HashSet, env, path::PathBuf};
+
+const MAX_BATCH_HASHES: usize = 1000;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BatchOperationRequest {
+    Delete,
+    Trash,
+    Restore,
+    AddToAlbum { album_id: i64 },
+    SetLocation { location: Option<Location> },
+    Update { changes: UpdateImageRequest },
+}
+
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    pub hashes: Vec<String>,
+    pub operation: BatchOperationRequest,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchItemStatus {
+    Ok,
+    NotFound, // unknown hash, or not in a state the operation applies to
+}
+
+#[derive(Serialize)]
+pub struct BatchItemResult {
+    pub hash: String,
+    pub status: BatchItemStatus,
+}
+
+#[derive(Serialize)]
+pub struct BatchResponse {
+    pub results: Vec<BatchItemResult>,
+}
+
+pub async fn batch_images(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Json(request): Json<BatchRequest>,
+) -> Result<Json<BatchResponse>, StatusCode> {
+    if request.hashes.len() > MAX_BATCH_HASHES {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let operation = match request.operation {
+        BatchOperationRequest::Delete => BatchOperation::Delete,
+        BatchOperationRequest::Trash => BatchOperation::Trash,
+        BatchOperationRequest::Restore => BatchOperation::Restore,
+        BatchOperationRequest::AddToAlbum { album_id } => {
+            let album = get_album(&pool, album_id, &claims.sub)
+                .await
+                .map_err(|e| {
+                    eprintln!("Database error: {:?}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?
+                .ok_or(StatusCode::NOT_FOUND)?;
+            if !album.role.can_add() {
+                return Err(StatusCode::FORBIDDEN);
+            }
+            BatchOperation::AddToAlbum(album_id)
+        }
+        BatchOperationRequest::SetLocation { location } => {
+            if location.is_some_and(|l| !l.is_valid()) {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            BatchOperation::Update(ImageUpdate {
+                location: Some(location),
+                ..Default::default()
+            })
+        }
+        BatchOperationRequest::Update { changes } => BatchOperation::Update(changes.into_update()?),
+    };
+
+    // Keep the request order but apply each hash once
+    let mut seen = HashSet::new();
+    let hashes: Vec<String> = request
+        .hashes
+        .into_iter()
+        .filter(|h| seen.insert(h.clone()))
+        .collect();
+
+    let outcome = apply_batch(&pool, &claims.sub, &hashes, &operation)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // Files are removed only once the rows are gone for good
+    if !outcome.deleted_files.is_empty() {
+        let storage_path =
+            env::var("IMAGE_STORAGE_PATH").map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        for (hash, extension) in &outcome.deleted_files {
+            let file_path = PathBuf::from(&storage_path).join(format!("{}.{}", hash, extension));
+
+            if let Err(e) = tokio::fs::remove_file(&file_path).await {
+                eprintln!(
+                    "Warning: Could not delete file {}: {:?}",
+                    file_path.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    let affected: HashSet<&String> = outcome.affected.iter().collect();
+    let results = hashes
+        .iter()
+        .map(|hash| BatchItemResult {
+            hash: hash.clone(),
+            status: if affected.contains(hash) {
+                BatchItemStatus::Ok
+            } else {
+                BatchItemStatus::NotFound
+            },
+        })
+        .collect();
+
+    Ok(Json(BatchResponse { results }))
+}
@@ -0,0 +1,173 @@
+use crate::db::{
+    create_device, delete_device, get_device, get_device_files, get_devices, record_sync,
+};
+use crate::routes::auth::Claims;
+use crate::types::{Device, DeviceFile, SyncStatus};
+use axum::{Extension, Json, extract::Path, extract::Query, extract::State, http::StatusCode};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+/// Devices that haven't completed a sync for this long are reported as stale
+const STALE_AFTER_DAYS: i64 = 7;
+
+const DEFAULT_FILES_LIMIT: i64 = 1000;
+const MAX_FILES_LIMIT: i64 = 5000;
+
+#[derive(Serialize)]
+pub struct DeviceResponse {
+    #[serde(flatten)]
+    pub device: Device,
+    pub stale: bool,
+}
+
+impl From<Device> for DeviceResponse {
+    fn from(device: Device) -> Self {
+        let stale = device
+            .last_success_at
+            .is_none_or(|t| t < Utc::now() - Duration::days(STALE_AFTER_DAYS));
+
+        DeviceResponse { device, stale }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateDeviceRequest {
+    pub name: String,
+    pub platform: Option<String>,
+}
+
+pub async fn create_device_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Json(request): Json<CreateDeviceRequest>,
+) -> Result<(StatusCode, Json<DeviceResponse>), StatusCode> {
+    if request.name.trim().is_empty()
+        || request.name.len() > 255
+        || request.platform.as_ref().is_some_and(|p| p.len() > 64)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let device = create_device(
+        &pool,
+        &claims.sub,
+        request.name.trim(),
+        request.platform.as_deref(),
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok((StatusCode::CREATED, Json(DeviceResponse::from(device))))
+}
+
+#[derive(Serialize)]
+pub struct GetDevicesResponse {
+    pub devices: Vec<DeviceResponse>,
+}
+
+pub async fn get_devices_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<GetDevicesResponse>, StatusCode> {
+    let devices = get_devices(&pool, &claims.sub).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(GetDevicesResponse {
+        devices: devices.into_iter().map(DeviceResponse::from).collect(),
+    }))
+}
+
+pub async fn delete_device_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+    let deleted = delete_device(&pool, id, &claims.sub).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if deleted {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RecordSyncRequest {
+    pub status: SyncStatus,
+    pub cursor: Option<i64>, // journal cursor the device has caught up to
+}
+
+pub async fn record_sync_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+    Json(request): Json<RecordSyncRequest>,
+) -> Result<Json<DeviceResponse>, StatusCode> {
+    if !claims.allows_device(id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let device = record_sync(&pool, id, &claims.sub, request.status, request.cursor)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(DeviceResponse::from(device)))
+}
+
+#[derive(Deserialize)]
+pub struct GetDeviceFilesQuery {
+    pub after: Option<String>, // last `relative_path` of the previous page
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct GetDeviceFilesResponse {
+    pub files: Vec<DeviceFile>,
+}
+
+/// Files uploaded from a device with their original paths, used to restore a library
+pub async fn get_device_files_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+    Query(query): Query<GetDeviceFilesQuery>,
+) -> Result<Json<GetDeviceFilesResponse>, StatusCode> {
+    if !claims.allows_device(id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    get_device(&pool, id, &claims.sub)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_FILES_LIMIT)
+        .clamp(1, MAX_FILES_LIMIT);
+
+    let files = get_device_files(&pool, id, &claims.sub, query.after.as_deref(), limit)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(GetDeviceFilesResponse { files }))
+}
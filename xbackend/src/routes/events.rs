@@ -0,0 +1,71 @@
+use crate::db::{get_event, get_event_images, get_events};
+use crate::routes::albums::GetAlbumQuery;
+use crate::routes::auth::Claims;
+use crate::routes::image::ImageMetadataResponse;
+use crate::types::Event;
+use axum::{Extension, Json, extract::Path, extract::Query, extract::State, http::StatusCode};
+use serde::Serialize;
+use sqlx::PgPool;
+
+const DEFAULT_EVENT_IMAGES_LIMIT: i64 = 100;
+const MAX_EVENT_IMAGES_LIMIT: i64 = 500;
+
+#[derive(Serialize)]
+pub struct GetEventsResponse {
+    pub events: Vec<Event>,
+}
+
+/// Events found in the library, read-only albums kept up to date by the server
+pub async fn get_events_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<GetEventsResponse>, StatusCode> {
+    let events = get_events(&pool, &claims.sub).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(GetEventsResponse { events }))
+}
+
+#[derive(Serialize)]
+pub struct GetEventResponse {
+    #[serde(flatten)]
+    pub event: Event,
+    pub images: Vec<ImageMetadataResponse>,
+}
+
+pub async fn get_event_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+    Query(query): Query<GetAlbumQuery>,
+) -> Result<Json<GetEventResponse>, StatusCode> {
+    let event = get_event(&pool, id, &claims.sub)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_EVENT_IMAGES_LIMIT)
+        .clamp(1, MAX_EVENT_IMAGES_LIMIT);
+
+    let images = get_event_images(&pool, id, query.offset.max(0), limit)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(GetEventResponse {
+        event,
+        images: images
+            .into_iter()
+            .map(ImageMetadataResponse::from)
+            .collect(),
+    }))
+}
@@ -0,0 +1,210 @@
+use crate::db::{get_images_near, get_map_clusters};
+use crate::routes::auth::Claims;
+use crate::routes::image::{ImageMetadataResponse, ListImagesResponse, page_images};
+use crate::types::{BoundingBox, ImageFilter, ImageSort, Location, MapCluster};
+use axum::{Extension, Json, extract::Query, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+const DEFAULT_NEARBY_LIMIT: i64 = 100;
+const MAX_NEARBY_LIMIT: i64 = 500;
+const MAX_RADIUS_KM: f64 = 20_000.0; // about half the circumference of the earth
+const MAX_ZOOM: u8 = 22;
+const CELLS_PER_TILE: f64 = 8.0; // grid cells across a 256px map tile
+const MAX_CLUSTERS: f64 = 1000.0;
+
+fn viewport(
+    min_latitude: f64,
+    min_longitude: f64,
+    max_latitude: f64,
+    max_longitude: f64,
+) -> Result<BoundingBox, StatusCode> {
+    let bbox = BoundingBox {
+        min_latitude,
+        min_longitude,
+        max_latitude,
+        max_longitude,
+    };
+
+    if bbox.is_valid() {
+        Ok(bbox)
+    } else {
+        Err(StatusCode::BAD_REQUEST)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BoundingBoxQuery {
+    // Spelled out, query strings can't be flattened into non-string fields
+    pub min_latitude: f64,
+    pub min_longitude: f64,
+    pub max_latitude: f64,
+    pub max_longitude: f64,
+    #[serde(default)]
+    pub sort: ImageSort,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Images inside a map viewport, paged like the regular listing
+pub async fn get_images_in_bbox(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<BoundingBoxQuery>,
+) -> Result<Json<ListImagesResponse>, StatusCode> {
+    let bbox = viewport(
+        query.min_latitude,
+        query.min_longitude,
+        query.max_latitude,
+        query.max_longitude,
+    )?;
+
+    let filter = ImageFilter {
+        bbox: Some(bbox),
+        ..Default::default()
+    };
+
+    let page = page_images(
+        &pool,
+        &claims.sub,
+        &filter,
+        query.sort,
+        query.cursor.as_deref(),
+        query.limit,
+    )
+    .await?;
+
+    Ok(Json(page))
+}
+
+#[derive(Deserialize)]
+pub struct NearbyQuery {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_km: f64,
+    #[serde(default)]
+    pub offset: i64,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct NearbyImageResponse {
+    #[serde(flatten)]
+    pub image: ImageMetadataResponse,
+    pub distance_km: f64,
+}
+
+#[derive(Serialize)]
+pub struct NearbyImagesResponse {
+    pub images: Vec<NearbyImageResponse>,
+}
+
+pub async fn get_nearby_images(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<NearbyQuery>,
+) -> Result<Json<NearbyImagesResponse>, StatusCode> {
+    let center = Location {
+        latitude: query.latitude,
+        longitude: query.longitude,
+    };
+
+    if !(center.is_valid() && query.radius_km > 0.0 && query.radius_km <= MAX_RADIUS_KM) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_NEARBY_LIMIT)
+        .clamp(1, MAX_NEARBY_LIMIT);
+
+    let images = get_images_near(
+        &pool,
+        &claims.sub,
+        center,
+        query.radius_km,
+        query.offset.max(0),
+        limit,
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(NearbyImagesResponse {
+        images: images
+            .into_iter()
+            .map(|(image, distance_km)| NearbyImageResponse {
+                image: ImageMetadataResponse::from(image),
+                distance_km,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ClustersQuery {
+    pub min_latitude: f64,
+    pub min_longitude: f64,
+    pub max_latitude: f64,
+    pub max_longitude: f64,
+    pub zoom: u8,
+}
+
+#[derive(Serialize)]
+pub struct ClustersResponse {
+    pub clusters: Vec<MapCluster>,
+}
+
+/// Number of grid cells the viewport touches, the most clusters it can have
+fn grid_cells(bbox: &BoundingBox, cell_size: f64) -> f64 {
+    let cells = |min: f64, max: f64| (max / cell_size).floor() - (min / cell_size).floor() + 1.0;
+
+    let columns: f64 = bbox
+        .longitude_ranges()
+        .into_iter()
+        .map(|(min, max)| cells(min, max))
+        .sum();
+
+    cells(bbox.min_latitude, bbox.max_latitude) * columns
+}
+
+/// Map markers for a viewport, the grid gets finer as the client zooms in
+pub async fn get_clusters(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<ClustersQuery>,
+) -> Result<Json<ClustersResponse>, StatusCode> {
+    if query.zoom > MAX_ZOOM {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let bbox = viewport(
+        query.min_latitude,
+        query.min_longitude,
+        query.max_latitude,
+        query.max_longitude,
+    )?;
+
+    // A web mercator tile spans 360 / 2^zoom degrees of longitude. A viewport
+    // too large for the zoom gets coarser cells, so no photo is left off the map
+    let mut cell_size = 360.0 / f64::from(1u32 << query.zoom) / CELLS_PER_TILE;
+    while grid_cells(&bbox, cell_size) > MAX_CLUSTERS {
+        cell_size *= 2.0;
+    }
+
+    let filter = ImageFilter {
+        bbox: Some(bbox),
+        ..Default::default()
+    };
+
+    let clusters = get_map_clusters(&pool, &claims.sub, &filter, cell_size)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(ClustersResponse { clusters }))
+}
@@ -1,11 +1,18 @@
-use crate::db::{delete_image, get_image_by_hash, get_image_hashes_by_owner, insert_image};
-use crate::img::{compute_hash, extract_gps_numeric};
+use crate::db::{
+    add_image_tags, delete_image, exceeds_quota, get_device, get_existing_hashes,
+    get_image_by_hash, get_image_hashes_by_owner, insert_image, list_images, normalize_tag,
+    record_source, update_image,
+};
+use crate::img::{compute_hash, extract_metadata};
 use crate::routes::auth::Claims;
-use crate::types::Image;
-use axum::{Extension, Json, extract::Path, extract::State, http::StatusCode};
+use crate::types::{
+    CursorKey, Image, ImageCursor, ImageFilter, ImageSort, ImageSource, ImageUpdate, Location,
+    MediaType, Place,
+};
+use axum::{Extension, Json, extract::Path, extract::Query, extract::State, http::StatusCode};
 use base64::{Engine as _, engine::general_purpose};
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::PgPool;
 use std::{env, path::PathBuf};
 
@@ -16,6 +23,57 @@ pub struct UploadImageRequest {
     pub image_name: String,
     pub created_at: DateTime<Utc>,
     pub modified_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub source: SourceFields,
+}
+
+/// Optional provenance sent along with an upload
+#[derive(Deserialize, Default)]
+pub struct SourceFields {
+    pub device_id: Option<i64>,
+    pub relative_path: Option<String>,
+    pub source_folder: Option<String>,
+}
+
+impl SourceFields {
+    /// Validate the fields, the device has to be one of the owner's and the
+    /// one an API token acts for
+    pub async fn resolve(
+        self,
+        pool: &PgPool,
+        claims: &Claims,
+    ) -> Result<Option<ImageSource>, StatusCode> {
+        let (device_id, relative_path) = match (self.device_id, self.relative_path) {
+            (Some(device_id), Some(relative_path)) => (device_id, relative_path),
+            (None, None) => return Ok(None),
+            _ => return Err(StatusCode::BAD_REQUEST),
+        };
+
+        if relative_path.is_empty()
+            || relative_path.len() > 1024
+            || self.source_folder.as_ref().is_some_and(|f| f.len() > 1024)
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        if !claims.allows_device(device_id) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        get_device(pool, device_id, &claims.sub)
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .ok_or(StatusCode::BAD_REQUEST)?;
+
+        Ok(Some(ImageSource {
+            device_id,
+            relative_path,
+            source_folder: self.source_folder,
+        }))
+    }
 }
 
 #[derive(Serialize)]
@@ -38,6 +96,8 @@ pub async fn upload_image(
     let storage_path =
         env::var("IMAGE_STORAGE_PATH").map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
+    let source = request.source.resolve(&pool, &claims).await?;
+
     // Decode base64 content
     let body = general_purpose::STANDARD
         .decode(&request.content)
@@ -45,6 +105,17 @@ pub async fn upload_image(
 
     let hash = compute_hash(&body);
 
+    let over_quota = exceeds_quota(&pool, &claims.sub, &hash, body.len() as i64)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if over_quota {
+        return Err(StatusCode::INSUFFICIENT_STORAGE);
+    }
+
     let file_name = format!("{}.{}", hash, request.extension);
     let file_path = PathBuf::from(&storage_path).join(&file_name);
 
@@ -52,50 +123,139 @@ pub async fn upload_image(
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    // Extract GPS coordinates from EXIF data
-    let (latitude, longitude) = extract_gps_numeric(&body);
-
-    // Create image record
-    let image = Image {
-        hash: hash.clone(),
+    let upload = NewUpload {
+        hash,
         extension: request.extension,
         owner: claims.sub,
         image_name: Some(request.image_name),
         created_at: request.created_at,
         modified_at: request.modified_at,
-        longitude,
-        latitude,
+        source,
     };
 
-    // Insert into database
-    let result = insert_image(&pool, &image).await;
+    let registered = register_upload(&pool, upload, &body, body.len() as i64)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // Another upload used up the quota since the check above
+    let Some((image, created)) = registered else {
+        if let Err(e) = tokio::fs::remove_file(&file_path).await {
+            eprintln!(
+                "Warning: Could not delete file {}: {:?}",
+                file_path.display(),
+                e
+            );
+        }
+
+        return Err(StatusCode::INSUFFICIENT_STORAGE);
+    };
 
     let response = UploadImageResponse {
-        hash: image.hash.clone(),
-        extension: image.extension.clone(),
-        owner: image.owner.clone(),
-        image_name: image.image_name.clone(),
+        hash: image.hash,
+        extension: image.extension,
+        owner: image.owner,
+        image_name: image.image_name,
         longitude: image.longitude,
         latitude: image.latitude,
         created_at: image.created_at,
         modified_at: image.modified_at,
     };
 
-    match result {
-        Ok(_) => Ok((StatusCode::CREATED, Json(response))),
-        Err(e) => {
-            // Check for duplicate key constraint violation
-            if let sqlx::Error::Database(db_err) = &e {
-                if db_err.code().as_deref() == Some("23505") {
-                    // Return conflict status but still include the metadata
-                    return Ok((StatusCode::CONFLICT, Json(response)));
-                }
-            }
+    if created {
+        Ok((StatusCode::CREATED, Json(response)))
+    } else {
+        // Return conflict status but still include the metadata
+        Ok((StatusCode::CONFLICT, Json(response)))
+    }
+}
 
-            eprintln!("Database error: {:?}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+/// A file that has been written to storage under its hash
+pub struct NewUpload {
+    pub hash: String,
+    pub extension: String,
+    pub owner: String,
+    pub image_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub modified_at: DateTime<Utc>,
+    pub source: Option<ImageSource>,
+}
+
+/// Run a stored file through the metadata pipeline and record it, `head` is
+/// the file or at least its first `METADATA_HEAD_SIZE` bytes. The flag is
+/// false when the image already existed, `None` when the owner is over quota
+pub async fn register_upload(
+    pool: &PgPool,
+    upload: NewUpload,
+    head: &[u8],
+    size_bytes: i64,
+) -> Result<Option<(Image, bool)>, sqlx::Error> {
+    // Extract location, capture time, camera and embedded labels from the file
+    let metadata = extract_metadata(head);
+
+    // Create image record
+    let image = Image {
+        hash: upload.hash.clone(),
+        media_type: MediaType::from_extension(&upload.extension),
+        extension: upload.extension.clone(),
+        owner: upload.owner.clone(),
+        image_name: upload.image_name.clone(),
+        created_at: upload.created_at,
+        modified_at: upload.modified_at,
+        longitude: metadata.longitude,
+        latitude: metadata.latitude,
+        width: metadata.width,
+        height: metadata.height,
+        taken_at: metadata.taken_at,
+        camera_model: metadata.camera_model,
+        description: None,
+        taken_at_override: None,
+        manual_latitude: None,
+        manual_longitude: None,
+        deleted_at: None,
+        favorite: false,
+        rating: metadata.rating,
+        place: None,
+        edited_at: None,
+    };
+
+    // Insert into database
+    let created = match insert_image(pool, &image, metadata.taken_at_offset, size_bytes).await {
+        Ok(true) => true,
+        Ok(false) => return Ok(None),
+        // Check for duplicate key constraint violation
+        Err(sqlx::Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => false,
+        Err(e) => return Err(e),
+    };
+
+    // Embedded keywords only seed the tags of a new image, later edits are the user's
+    if created {
+        let mut tags: Vec<String> = metadata
+            .keywords
+            .iter()
+            .filter_map(|k| normalize_tag(k))
+            .collect();
+        tags.sort();
+        tags.dedup();
+
+        if !tags.is_empty() {
+            add_image_tags(pool, &image.hash, &image.owner, &tags).await?;
+        }
+    }
+
+    // Duplicates are linked too, the same file may sit in several folders
+    if let Some(source) = &upload.source {
+        match record_source(pool, &image.owner, &image.hash, source).await {
+            Ok(_) => {}
+            // The file is stored for another user, there is nothing to link
+            Err(sqlx::Error::Database(db_err)) if db_err.is_foreign_key_violation() => {}
+            Err(e) => return Err(e),
         }
     }
+
+    Ok(Some((image, created)))
 }
 
 #[derive(Serialize)]
@@ -117,6 +277,256 @@ pub async fn get_user_image_hashes(
     Ok(Json(GetImageHashesResponse { hashes }))
 }
 
+const DEFAULT_LIST_LIMIT: i64 = 100;
+const MAX_LIST_LIMIT: i64 = 500;
+
+/// Image record without the file content
+#[derive(Serialize)]
+pub struct ImageMetadataResponse {
+    pub hash: String,
+    pub extension: String,
+    pub owner: String,
+    pub image_name: Option<String>,
+    pub media_type: MediaType,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub longitude: Option<f64>,
+    pub latitude: Option<f64>,
+    pub taken_at: Option<DateTime<Utc>>,
+    pub captured_at: DateTime<Utc>,
+    pub camera_model: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub modified_at: DateTime<Utc>,
+    pub description: Option<String>,
+    pub taken_at_override: Option<DateTime<Utc>>,
+    pub manual_latitude: Option<f64>,
+    pub manual_longitude: Option<f64>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub favorite: bool,
+    pub rating: Option<i16>,
+    pub place: Option<Place>,
+    pub edited_at: Option<DateTime<Utc>>,
+}
+
+impl From<Image> for ImageMetadataResponse {
+    fn from(image: Image) -> Self {
+        ImageMetadataResponse {
+            captured_at: image.captured_at(),
+            hash: image.hash,
+            extension: image.extension,
+            owner: image.owner,
+            image_name: image.image_name,
+            media_type: image.media_type,
+            width: image.width,
+            height: image.height,
+            longitude: image.longitude,
+            latitude: image.latitude,
+            taken_at: image.taken_at,
+            camera_model: image.camera_model,
+            created_at: image.created_at,
+            modified_at: image.modified_at,
+            description: image.description,
+            taken_at_override: image.taken_at_override,
+            manual_latitude: image.manual_latitude,
+            manual_longitude: image.manual_longitude,
+            deleted_at: image.deleted_at,
+            favorite: image.favorite,
+            rating: image.rating,
+            place: image.place,
+            edited_at: image.edited_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ListImagesQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub media_type: Option<MediaType>,
+    #[serde(default)]
+    pub trashed: bool,
+    #[serde(default)]
+    pub sort: ImageSort,
+    pub tag: Option<String>,
+    pub favorite: Option<bool>,
+    pub min_rating: Option<i16>,
+    pub owner: Option<String>, // e.g. a partner whose library isn't in the timeline
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ListImagesResponse {
+    pub images: Vec<ImageMetadataResponse>,
+    pub next_cursor: Option<String>, // None on the last page
+}
+
+pub async fn list_images_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<ListImagesQuery>,
+) -> Result<Json<ListImagesResponse>, StatusCode> {
+    if query.min_rating.is_some_and(|r| !valid_rating(r)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let filter = ImageFilter {
+        from: query.from,
+        to: query.to,
+        media_type: query.media_type,
+        trashed: query.trashed,
+        tags: query
+            .tag
+            .as_deref()
+            .map(|t| normalize_tag(t).ok_or(StatusCode::BAD_REQUEST))
+            .transpose()?
+            .into_iter()
+            .collect(),
+        favorite: query.favorite,
+        min_rating: query.min_rating,
+        owner: query.owner,
+        ..Default::default()
+    };
+
+    let page = page_images(
+        &pool,
+        &claims.sub,
+        &filter,
+        query.sort,
+        query.cursor.as_deref(),
+        query.limit,
+    )
+    .await?;
+
+    Ok(Json(page))
+}
+
+/// Fetch one page of a listing and the cursor of the next one
+pub async fn page_images(
+    pool: &PgPool,
+    username: &str,
+    filter: &ImageFilter,
+    sort: ImageSort,
+    cursor: Option<&str>,
+    limit: Option<i64>,
+) -> Result<ListImagesResponse, StatusCode> {
+    let limit = limit.unwrap_or(DEFAULT_LIST_LIMIT).clamp(1, MAX_LIST_LIMIT);
+
+    let cursor = match cursor {
+        Some(cursor) => Some(
+            decode_cursor(cursor)
+                .filter(|c| sort.accepts(c))
+                .ok_or(StatusCode::BAD_REQUEST)?,
+        ),
+        None => None,
+    };
+
+    let images = list_images(pool, username, filter, sort, cursor.as_ref(), limit)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let next_cursor = match images.last() {
+        Some(last) if images.len() as i64 == limit => Some(encode_cursor(&sort.cursor_for(last))),
+        _ => None,
+    };
+
+    Ok(ListImagesResponse {
+        images: images
+            .into_iter()
+            .map(ImageMetadataResponse::from)
+            .collect(),
+        next_cursor,
+    })
+}
+
+/// Cursors are opaque to clients: base64url of `<hash>:<kind>:<sort value>`
+pub fn encode_cursor(cursor: &ImageCursor) -> String {
+    let raw = match &cursor.key {
+        CursorKey::Time(time) => format!("{}:t:{}", cursor.hash, time.to_rfc3339()),
+        CursorKey::Text(text) => format!("{}:s:{}", cursor.hash, text),
+        CursorKey::Number(number) => format!("{}:n:{}", cursor.hash, number),
+    };
+
+    general_purpose::URL_SAFE_NO_PAD.encode(raw)
+}
+
+fn decode_cursor(cursor: &str) -> Option<ImageCursor> {
+    let raw = general_purpose::URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    let raw = String::from_utf8(raw).ok()?;
+    let mut parts = raw.splitn(3, ':');
+
+    let hash = parts.next()?.to_string();
+    let key = match (parts.next()?, parts.next()?) {
+        ("t", value) => CursorKey::Time(DateTime::parse_from_rfc3339(value).ok()?.to_utc()),
+        ("s", value) => CursorKey::Text(value.to_string()),
+        ("n", value) => CursorKey::Number(value.parse().ok()?),
+        _ => return None,
+    };
+
+    Some(ImageCursor { key, hash })
+}
+
+const MAX_EXISTS_HASHES: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct ImagesExistRequest {
+    pub hashes: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImagesExistResponse {
+    pub existing: Vec<String>,
+    pub missing: Vec<String>,
+    pub unavailable: Vec<String>, // stored under another account, an upload would be refused
+}
+
+pub async fn images_exist(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Json(request): Json<ImagesExistRequest>,
+) -> Result<Json<ImagesExistResponse>, StatusCode> {
+    if request.hashes.len() > MAX_EXISTS_HASHES {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    // Hashes are stored as lowercase hex, normalize before comparing
+    let mut seen = std::collections::HashSet::new();
+    let hashes: Vec<String> = request
+        .hashes
+        .iter()
+        .map(|h| h.trim().to_ascii_lowercase())
+        .filter(|h| seen.insert(h.clone()))
+        .collect();
+
+    let stored: std::collections::HashMap<String, bool> =
+        get_existing_hashes(&pool, &claims.sub, &hashes)
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .into_iter()
+            .collect();
+
+    let mut response = ImagesExistResponse {
+        existing: Vec::new(),
+        missing: Vec::new(),
+        unavailable: Vec::new(),
+    };
+    for hash in hashes {
+        match stored.get(&hash) {
+            Some(true) => response.existing.push(hash),
+            Some(false) => response.unavailable.push(hash),
+            None => response.missing.push(hash),
+        }
+    }
+
+    Ok(Json(response))
+}
+
 #[derive(Serialize)]
 pub struct GetImageResponse {
     pub hash: String,
@@ -171,6 +581,95 @@ pub async fn get_image(
     }))
 }
 
+#[derive(Deserialize)]
+pub struct UpdateImageRequest {
+    pub image_name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub description: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub taken_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub location: Option<Option<Location>>,
+    pub favorite: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub rating: Option<Option<i16>>,
+}
+
+/// Star ratings go from 1 to 5, unrated is `null`
+pub fn valid_rating(rating: i16) -> bool {
+    (1..=5).contains(&rating)
+}
+
+/// Tells an explicit `null` (`Some(None)`) apart from a missing field (`None`)
+pub fn deserialize_nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Ok(Some(Option::deserialize(deserializer)?))
+}
+
+impl UpdateImageRequest {
+    pub fn into_update(self) -> Result<ImageUpdate, StatusCode> {
+        // An edit that changes nothing would still be journaled
+        if self.image_name.is_none()
+            && self.description.is_none()
+            && self.taken_at.is_none()
+            && self.location.is_none()
+            && self.favorite.is_none()
+            && self.rating.is_none()
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        if let Some(name) = &self.image_name
+            && (name.trim().is_empty() || name.len() > 255)
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        if let Some(Some(location)) = &self.location
+            && !location.is_valid()
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        if let Some(Some(rating)) = self.rating
+            && !valid_rating(rating)
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        Ok(ImageUpdate {
+            image_name: self.image_name,
+            description: self.description,
+            taken_at: self.taken_at,
+            location: self.location,
+            favorite: self.favorite,
+            rating: self.rating,
+        })
+    }
+}
+
+pub async fn update_image_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(hash): Path<String>,
+    Json(request): Json<UpdateImageRequest>,
+) -> Result<Json<ImageMetadataResponse>, StatusCode> {
+    let update = request.into_update()?;
+
+    let image = update_image(&pool, &hash, &claims.sub, &update)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(ImageMetadataResponse::from(image)))
+}
+
 #[derive(Serialize)]
 pub struct DeleteImageResponse {
     pub success: bool,
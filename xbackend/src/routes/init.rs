@@ -1,26 +1,147 @@
 use axum::{
-    Router, extract::DefaultBodyLimit, middleware, routing::delete, routing::get, routing::post,
+    Extension, Router, extract::DefaultBodyLimit, middleware, routing::delete, routing::get,
+    routing::patch, routing::post, routing::put,
 };
 
+use crate::db::listen_changes;
+use crate::events::run_event_grouping;
+use crate::geocode::run_geocoder;
+use crate::img::backfill_sizes;
 use crate::routes::{
-    auth::login, auth_middleware, get_image, get_user_image_hashes, health::health,
-    image::delete_image_endpoint, image::upload_image,
+    albums::add_album_images_endpoint, albums::create_album_endpoint,
+    albums::delete_album_endpoint, albums::get_album_endpoint, albums::get_album_members_endpoint,
+    albums::get_albums_endpoint, albums::remove_album_images_endpoint,
+    albums::remove_album_member_endpoint, albums::reorder_album_endpoint,
+    albums::set_album_member_endpoint, albums::update_album_endpoint, auth::login, auth::refresh,
+    auth::session_middleware, auth_middleware, batch::batch_images,
+    devices::create_device_endpoint, devices::delete_device_endpoint,
+    devices::get_device_files_endpoint, devices::get_devices_endpoint,
+    devices::record_sync_endpoint, events::get_event_endpoint, events::get_events_endpoint,
+    geo::get_clusters, geo::get_images_in_bbox, geo::get_nearby_images, get_image,
+    get_user_image_hashes, health::health, image::delete_image_endpoint, image::images_exist,
+    image::list_images_endpoint, image::update_image_endpoint, image::upload_image,
+    memories::get_memories_endpoint, partners::get_partners_endpoint,
+    partners::leave_shared_library_endpoint, partners::remove_partner_endpoint,
+    partners::set_partner_endpoint, partners::update_shared_library_endpoint, places::get_places,
+    register::create_invite_endpoint, register::register, search::search_images,
+    sessions::delete_session_endpoint, sessions::get_sessions_endpoint, sessions::logout,
+    sessions::logout_all, shares::create_share_endpoint, shares::download_shared_file,
+    shares::get_shared, shares::get_shared_file, shares::get_shares_endpoint,
+    shares::revoke_share_endpoint, shares::unlock_share, sync::change_events, sync::get_changes,
+    tags::add_image_tags_endpoint, tags::get_image_tags_endpoint, tags::get_tags,
+    tags::remove_image_tag_endpoint, timeline::get_timeline_endpoint,
+    tokens::create_token_endpoint, tokens::get_tokens_endpoint, tokens::revoke_token_endpoint,
+    upload::upload_images,
 };
+use crate::types::LibraryEvent;
+use tokio::sync::broadcast;
 
 pub async fn init(pool: sqlx::PgPool) {
+    // Committed library changes, fanned out to the event streams
+    let (events, _) = broadcast::channel::<LibraryEvent>(1024);
+    tokio::spawn(listen_changes(pool.clone(), events.clone()));
+    tokio::spawn(run_geocoder(pool.clone(), events.clone()));
+    tokio::spawn(run_event_grouping(pool.clone(), events.clone()));
+    tokio::spawn(backfill_sizes(pool.clone()));
+
     let app = Router::new()
         // Public routes - no authentication required
         .route("/health", get(health))
         .route("/login", post(login))
+        .route("/refresh", post(refresh))
+        .route("/register", post(register))
+        // Share links, the token grants access
+        .route("/s/{token}", get(get_shared))
+        .route("/s/{token}/unlock", post(unlock_share))
+        .route("/s/{token}/img/{hash}", get(get_shared_file))
+        .route("/s/{token}/img/{hash}/download", get(download_shared_file))
         // Protected routes - require authentication
         .merge(
             Router::new()
+                // What sync clients need, also open to API tokens
                 .route("/img", post(upload_image))
                 .route("/img/hashes", get(get_user_image_hashes))
+                .route("/img/exists", post(images_exist))
+                // Multipart uploads are limited per file and by their number of parts instead
+                .route(
+                    "/img/upload",
+                    post(upload_images).layer(DefaultBodyLimit::disable()),
+                )
                 .route("/img/{hash}", get(get_image))
-                .route("/img/{hash}", delete(delete_image_endpoint))
+                .route("/sync/changes", get(get_changes))
+                .route("/sync/events", get(change_events))
+                .route("/devices/{id}/sync", post(record_sync_endpoint))
+                .route("/devices/{id}/files", get(get_device_files_endpoint))
                 .route("/health-auth", get(health))
-                .layer(middleware::from_fn(auth_middleware))
+                // Everything else takes a login
+                .merge(
+                    Router::new()
+                        .route("/img", get(list_images_endpoint))
+                        .route("/img/batch", post(batch_images))
+                        .route("/img/search", post(search_images))
+                        .route("/img/{hash}", delete(delete_image_endpoint))
+                        .route("/img/{hash}", patch(update_image_endpoint))
+                        .route("/img/{hash}/tags", get(get_image_tags_endpoint))
+                        .route("/img/{hash}/tags", post(add_image_tags_endpoint))
+                        .route("/img/{hash}/tags/{tag}", delete(remove_image_tag_endpoint))
+                        .route("/tags", get(get_tags))
+                        .route("/geo/bbox", get(get_images_in_bbox))
+                        .route("/geo/nearby", get(get_nearby_images))
+                        .route("/geo/clusters", get(get_clusters))
+                        .route("/places", get(get_places))
+                        .route("/timeline", get(get_timeline_endpoint))
+                        .route("/memories", get(get_memories_endpoint))
+                        .route("/albums", get(get_albums_endpoint))
+                        .route("/albums", post(create_album_endpoint))
+                        .route("/albums/auto", get(get_events_endpoint))
+                        .route("/albums/auto/{id}", get(get_event_endpoint))
+                        .route("/albums/{id}", get(get_album_endpoint))
+                        .route("/albums/{id}", patch(update_album_endpoint))
+                        .route("/albums/{id}", delete(delete_album_endpoint))
+                        .route("/albums/{id}/images", post(add_album_images_endpoint))
+                        .route("/albums/{id}/images", delete(remove_album_images_endpoint))
+                        .route("/albums/{id}/order", put(reorder_album_endpoint))
+                        .route("/albums/{id}/members", get(get_album_members_endpoint))
+                        .route(
+                            "/albums/{id}/members/{username}",
+                            put(set_album_member_endpoint),
+                        )
+                        .route(
+                            "/albums/{id}/members/{username}",
+                            delete(remove_album_member_endpoint),
+                        )
+                        .route("/partners", get(get_partners_endpoint))
+                        .route("/partners/{username}", put(set_partner_endpoint))
+                        .route("/partners/{username}", delete(remove_partner_endpoint))
+                        .route(
+                            "/partners/from/{owner}",
+                            patch(update_shared_library_endpoint),
+                        )
+                        .route(
+                            "/partners/from/{owner}",
+                            delete(leave_shared_library_endpoint),
+                        )
+                        .route("/shares", get(get_shares_endpoint))
+                        .route("/shares", post(create_share_endpoint))
+                        .route("/shares/{id}", delete(revoke_share_endpoint))
+                        .route("/invites", post(create_invite_endpoint))
+                        .route("/logout", post(logout))
+                        .route("/logout/all", post(logout_all))
+                        .route("/sessions", get(get_sessions_endpoint))
+                        .route("/sessions/{id}", delete(delete_session_endpoint))
+                        .route("/tokens", get(get_tokens_endpoint))
+                        .route("/tokens", post(create_token_endpoint))
+                        .route("/tokens/{id}", delete(revoke_token_endpoint))
+                        .route("/devices", get(get_devices_endpoint))
+                        .route("/devices", post(create_device_endpoint))
+                        .route("/devices/{id}", delete(delete_device_endpoint))
+                        .route_layer(middleware::from_fn(session_middleware)),
+                )
+                .layer(Extension(events))
+                .layer(middleware::from_fn_with_state(
+                    pool.clone(),
+                    auth_middleware,
+                ))
                 .layer(DefaultBodyLimit::max(10 * 1024 * 1024)),
         )
         .with_state(pool.clone());
@@ -0,0 +1,78 @@
+use crate::db::{get_local_date, get_memories};
+use crate::routes::auth::Claims;
+use crate::routes::image::ImageMetadataResponse;
+use crate::routes::timeline::resolve_timezone;
+use axum::{Extension, Json, extract::Query, extract::State, http::StatusCode};
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+const DEFAULT_MEMORIES_PER_YEAR: i64 = 20;
+const MAX_MEMORIES_PER_YEAR: i64 = 100;
+
+#[derive(Deserialize)]
+pub struct MemoriesQuery {
+    pub date: Option<NaiveDate>, // today in `tz` by default
+    pub tz: Option<String>,
+    pub per_year: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct MemoryYear {
+    pub year: i32,
+    pub years_ago: i32,
+    pub images: Vec<ImageMetadataResponse>,
+}
+
+#[derive(Serialize)]
+pub struct MemoriesResponse {
+    pub date: NaiveDate,
+    pub years: Vec<MemoryYear>,
+}
+
+/// "On this day": images from the same calendar day in previous years
+pub async fn get_memories_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<MemoriesQuery>,
+) -> Result<Json<MemoriesResponse>, StatusCode> {
+    let tz = resolve_timezone(&pool, query.tz).await?;
+
+    let per_year = query
+        .per_year
+        .unwrap_or(DEFAULT_MEMORIES_PER_YEAR)
+        .clamp(1, MAX_MEMORIES_PER_YEAR);
+
+    let date = match query.date {
+        Some(date) => date,
+        None => get_local_date(&pool, &tz).await.map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?,
+    };
+
+    let memories = get_memories(&pool, &claims.sub, date, &tz, per_year)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // Rows come ordered by year
+    let mut years: Vec<MemoryYear> = Vec::new();
+    for (year, image) in memories {
+        if years.last().is_none_or(|y| y.year != year) {
+            years.push(MemoryYear {
+                year,
+                years_ago: date.year() - year,
+                images: Vec::new(),
+            });
+        }
+
+        if let Some(group) = years.last_mut() {
+            group.images.push(ImageMetadataResponse::from(image));
+        }
+    }
+
+    Ok(Json(MemoriesResponse { date, years }))
+}
@@ -1,8 +1,26 @@
+mod albums;
 mod auth;
+mod batch;
+mod devices;
+mod events;
+mod geo;
 mod health;
 mod image;
 mod init;
+mod memories;
+mod partners;
+mod places;
+mod register;
+mod search;
+mod sessions;
+mod shares;
+mod sync;
+mod tags;
+mod timeline;
+mod tokens;
+mod upload;
 
 pub use auth::auth_middleware;
 pub use image::{get_image, get_user_image_hashes};
 pub use init::init;
+pub use register::{valid_password, valid_username};
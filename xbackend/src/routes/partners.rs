@@ -0,0 +1,126 @@
+use crate::db::{
+    delete_partner_share, get_partner_shares, set_partner_in_timeline, set_partner_share,
+};
+use crate::routes::auth::Claims;
+use crate::types::PartnerShare;
+use axum::{Extension, Json, extract::Path, extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+#[derive(Serialize)]
+pub struct GetPartnersResponse {
+    pub shared_with: Vec<PartnerShare>, // libraries the user opened to others
+    pub shared_by: Vec<PartnerShare>,   // libraries the user can read
+}
+
+pub async fn get_partners_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<GetPartnersResponse>, StatusCode> {
+    let shares = get_partner_shares(&pool, &claims.sub).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let (shared_with, shared_by) = shares.into_iter().partition(|s| s.owner == claims.sub);
+
+    Ok(Json(GetPartnersResponse {
+        shared_with,
+        shared_by,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct SetPartnerRequest {
+    pub since: Option<DateTime<Utc>>, // share everything when missing
+}
+
+/// Give the partner read access to the whole library
+pub async fn set_partner_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(partner): Path<String>,
+    Json(request): Json<SetPartnerRequest>,
+) -> Result<Json<PartnerShare>, StatusCode> {
+    if partner == claims.sub {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let share = set_partner_share(&pool, &claims.sub, &partner, request.since)
+        .await
+        .map_err(|e| match e {
+            // No such user
+            sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() => {
+                StatusCode::NOT_FOUND
+            }
+            e => {
+                eprintln!("Database error: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+
+    Ok(Json(share))
+}
+
+pub async fn remove_partner_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(partner): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let deleted = delete_partner_share(&pool, &claims.sub, &partner)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if deleted {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateSharedLibraryRequest {
+    pub in_timeline: bool,
+}
+
+/// Merge a library shared with the user into their timeline, or take it out
+pub async fn update_shared_library_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(owner): Path<String>,
+    Json(request): Json<UpdateSharedLibraryRequest>,
+) -> Result<Json<PartnerShare>, StatusCode> {
+    let share = set_partner_in_timeline(&pool, &owner, &claims.sub, request.in_timeline)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(share))
+}
+
+/// Stop receiving a library someone shared with the user
+pub async fn leave_shared_library_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(owner): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let deleted = delete_partner_share(&pool, &owner, &claims.sub)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if deleted {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
@@ -0,0 +1,32 @@
+use crate::db::get_place_groups;
+use crate::routes::auth::Claims;
+use crate::types::{PlaceGroup, PlaceLevel};
+use axum::{Extension, Json, extract::Query, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+#[derive(Deserialize)]
+pub struct GetPlacesQuery {
+    #[serde(default)]
+    pub level: PlaceLevel,
+}
+
+#[derive(Serialize)]
+pub struct GetPlacesResponse {
+    pub places: Vec<PlaceGroup>,
+}
+
+pub async fn get_places(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<GetPlacesQuery>,
+) -> Result<Json<GetPlacesResponse>, StatusCode> {
+    let places = get_place_groups(&pool, &claims.sub, query.level)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(GetPlacesResponse { places }))
+}
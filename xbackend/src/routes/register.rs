@@ -0,0 +1,128 @@
+use crate::db::{UserError, create_invite, create_user, is_admin};
+use crate::routes::auth::Claims;
+use crate::secret::{generate_token, hash_token};
+use crate::types::{RegistrationMode, User, UserCredentials};
+use axum::{Extension, Json, extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::env;
+
+const MIN_USERNAME_LENGTH: usize = 3;
+const MAX_USERNAME_LENGTH: usize = 32;
+const MIN_PASSWORD_LENGTH: usize = 10;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Letters, digits, `.`, `_` and `-`, starting with a letter or digit
+pub fn valid_username(username: &str) -> bool {
+    (MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&username.len())
+        && username
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+pub fn valid_password(username: &str, password: &str) -> bool {
+    (MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&password.chars().count())
+        && !password.eq_ignore_ascii_case(username)
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub password: String,
+    pub invite_code: Option<String>, // needed when registration is by invite
+}
+
+pub async fn register(
+    State(pool): State<PgPool>,
+    Json(request): Json<RegisterRequest>,
+) -> Result<(StatusCode, Json<User>), StatusCode> {
+    // Anything unexpected keeps registration closed
+    let mode = env::var("REGISTRATION")
+        .ok()
+        .and_then(|value| RegistrationMode::parse(&value))
+        .unwrap_or_default();
+
+    let invite_hash = match (mode, &request.invite_code) {
+        (RegistrationMode::Disabled, _) => return Err(StatusCode::FORBIDDEN),
+        (RegistrationMode::Open, _) => None,
+        (RegistrationMode::Invite, Some(code)) => Some(hash_token(code)),
+        (RegistrationMode::Invite, None) => return Err(StatusCode::FORBIDDEN),
+    };
+
+    if !valid_username(&request.username) || !valid_password(&request.username, &request.password) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let credentials = UserCredentials {
+        username: request.username,
+        password: request.password,
+    };
+
+    match create_user(&pool, &credentials, invite_hash.as_deref()).await {
+        Ok(user) => Ok((StatusCode::CREATED, Json(user))),
+        Err(UserError::UsernameExists) => Err(StatusCode::CONFLICT),
+        Err(UserError::InvalidInvite) => Err(StatusCode::FORBIDDEN),
+        Err(e) => {
+            eprintln!("Registration error: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateInviteRequest {
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct CreateInviteResponse {
+    pub code: String, // only returned here, the server keeps a hash
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Admins only
+pub async fn create_invite_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Json(request): Json<CreateInviteRequest>,
+) -> Result<(StatusCode, Json<CreateInviteResponse>), StatusCode> {
+    let admin = is_admin(&pool, &claims.sub).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if !admin {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if request.expires_at.is_some_and(|t| t <= Utc::now()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let code = generate_token();
+
+    create_invite(
+        &pool,
+        Some(&claims.sub),
+        &hash_token(&code),
+        request.expires_at,
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateInviteResponse {
+            code,
+            expires_at: request.expires_at,
+        }),
+    ))
+}
@@ -0,0 +1,103 @@
+use crate::db::normalize_tag;
+use crate::routes::auth::Claims;
+use crate::routes::image::{ListImagesResponse, page_images, valid_rating};
+use crate::types::{BoundingBox, ImageFilter, ImageSort, MediaType};
+use axum::{Extension, Json, extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+const MAX_SEARCH_TAGS: usize = 20;
+const MAX_SEARCH_TEXT_LENGTH: usize = 255;
+
+/// Every filter is optional, the ones given are combined with AND
+#[derive(Deserialize)]
+pub struct SearchImagesRequest {
+    pub name: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub camera_model: Option<String>,
+    pub has_location: Option<bool>,
+    pub bbox: Option<BoundingBox>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub album_id: Option<i64>,
+    pub owner: Option<String>,
+    pub media_type: Option<MediaType>,
+    pub favorite: Option<bool>,
+    pub min_rating: Option<i16>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+    #[serde(default)]
+    pub trashed: bool,
+    #[serde(default)]
+    pub sort: ImageSort,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+impl SearchImagesRequest {
+    fn filter(&self) -> Result<ImageFilter, StatusCode> {
+        let text = |value: &Option<String>| match value.as_deref().map(str::trim) {
+            Some(value) if value.len() > MAX_SEARCH_TEXT_LENGTH => Err(StatusCode::BAD_REQUEST),
+            Some(value) if !value.is_empty() => Ok(Some(value.to_string())),
+            _ => Ok(None),
+        };
+
+        if self.tags.len() > MAX_SEARCH_TAGS
+            || self.bbox.is_some_and(|b| !b.is_valid())
+            || self.min_rating.is_some_and(|r| !valid_rating(r))
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        let mut tags = self
+            .tags
+            .iter()
+            .map(|t| normalize_tag(t))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        tags.sort();
+        tags.dedup();
+
+        Ok(ImageFilter {
+            from: self.from,
+            to: self.to,
+            media_type: self.media_type,
+            trashed: self.trashed,
+            tags,
+            favorite: self.favorite,
+            min_rating: self.min_rating,
+            name: text(&self.name)?,
+            camera_model: text(&self.camera_model)?,
+            has_location: self.has_location,
+            bbox: self.bbox,
+            album_id: self.album_id,
+            owner: self.owner.clone(),
+            country: text(&self.country)?,
+            region: text(&self.region)?,
+            city: text(&self.city)?,
+        })
+    }
+}
+
+pub async fn search_images(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Json(request): Json<SearchImagesRequest>,
+) -> Result<Json<ListImagesResponse>, StatusCode> {
+    let filter = request.filter()?;
+
+    let page = page_images(
+        &pool,
+        &claims.sub,
+        &filter,
+        request.sort,
+        request.cursor.as_deref(),
+        request.limit,
+    )
+    .await?;
+
+    Ok(Json(page))
+}
@@ -0,0 +1,540 @@
+use crate::db::{
+    create_share_link, get_active_share_link, get_album, get_album_image, get_album_images,
+    get_image_by_hash, get_share_links, revoke_share_link,
+};
+use crate::img::render_preview;
+use crate::routes::auth::{Claims, jwt_secret};
+use crate::secret::{generate_token, hash_password, hash_token, verify_password};
+use crate::types::{Image, MediaType, ShareLink, ShareTarget};
+use axum::{
+    Extension, Form, Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, path::PathBuf};
+
+const SHARE_PASSWORD_HEADER: &str = "X-Share-Password";
+/// Set by `POST /s/{token}/unlock` so a browser doesn't send the password again
+const SHARE_GRANT_COOKIE: &str = "share_grant";
+const SHARE_GRANT_LIFETIME: chrono::Duration = chrono::Duration::hours(12);
+/// `aud` of share grants, keeps them apart from access tokens signed with the same secret
+const SHARE_GRANT_AUDIENCE: &str = "share";
+/// Wrong passwords allowed per link within the window, then it answers `429`
+const MAX_PASSWORD_FAILURES: u32 = 10;
+const PASSWORD_FAILURE_WINDOW: Duration = Duration::from_secs(60);
+const DEFAULT_SHARED_IMAGES_LIMIT: i64 = 100;
+const MAX_SHARED_IMAGES_LIMIT: i64 = 500;
+/// Longest side of the preview served when downloads are off
+const SHARED_PREVIEW_SIZE: u32 = 1280;
+
+#[derive(Serialize)]
+pub struct ShareLinkResponse {
+    #[serde(flatten)]
+    pub link: ShareLink,
+    pub has_password: bool,
+}
+
+impl From<ShareLink> for ShareLinkResponse {
+    fn from(link: ShareLink) -> Self {
+        ShareLinkResponse {
+            has_password: link.password.is_some(),
+            link,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateShareRequest {
+    pub image_hash: Option<String>,
+    pub album_id: Option<i64>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub password: Option<String>,
+    #[serde(default = "default_allow_download")]
+    pub allow_download: bool,
+}
+
+fn default_allow_download() -> bool {
+    true
+}
+
+#[derive(Serialize)]
+pub struct CreateShareResponse {
+    #[serde(flatten)]
+    pub link: ShareLinkResponse,
+    pub token: String, // only returned here, the server keeps a hash
+}
+
+pub async fn create_share_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Json(request): Json<CreateShareRequest>,
+) -> Result<(StatusCode, Json<CreateShareResponse>), StatusCode> {
+    let target = match (request.image_hash, request.album_id) {
+        (Some(hash), None) => ShareTarget::Image(hash),
+        (None, Some(id)) => ShareTarget::Album(id),
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    if request.expires_at.is_some_and(|t| t <= Utc::now())
+        || request
+            .password
+            .as_ref()
+            .is_some_and(|p| p.is_empty() || p.len() > 255)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // Hashing is slow on purpose, it runs off the async runtime
+    let password = match request.password {
+        Some(password) => Some(
+            tokio::task::spawn_blocking(move || hash_password(&password))
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        ),
+        None => None,
+    };
+
+    let token = generate_token();
+
+    let link = create_share_link(
+        &pool,
+        &claims.sub,
+        &hash_token(&token),
+        &target,
+        password.as_deref(),
+        request.allow_download,
+        request.expires_at,
+    )
+    .await
+    .map_err(|e| match e {
+        // The image or album is not one of the caller's
+        sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() => StatusCode::NOT_FOUND,
+        e => {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateShareResponse {
+            link: ShareLinkResponse::from(link),
+            token,
+        }),
+    ))
+}
+
+#[derive(Serialize)]
+pub struct GetSharesResponse {
+    pub shares: Vec<ShareLinkResponse>,
+}
+
+pub async fn get_shares_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<GetSharesResponse>, StatusCode> {
+    let links = get_share_links(&pool, &claims.sub).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(GetSharesResponse {
+        shares: links.into_iter().map(ShareLinkResponse::from).collect(),
+    }))
+}
+
+pub async fn revoke_share_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+    let revoked = revoke_share_link(&pool, id, &claims.sub)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if revoked {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+/// Recent wrong passwords per link: when the first one in the window was
+/// given and how many followed
+static PASSWORD_FAILURES: LazyLock<Mutex<HashMap<i64, (Instant, u32)>>> =
+    LazyLock::new(Default::default);
+
+fn too_many_failures(link_id: i64) -> bool {
+    let failures = PASSWORD_FAILURES.lock().unwrap();
+    failures.get(&link_id).is_some_and(|(since, count)| {
+        since.elapsed() < PASSWORD_FAILURE_WINDOW && *count >= MAX_PASSWORD_FAILURES
+    })
+}
+
+fn record_failure(link_id: i64) {
+    let mut failures = PASSWORD_FAILURES.lock().unwrap();
+    failures.retain(|_, (since, _)| since.elapsed() < PASSWORD_FAILURE_WINDOW);
+
+    let (_, count) = failures.entry(link_id).or_insert((Instant::now(), 0));
+    *count += 1;
+}
+
+/// Check a password against the link's, hashing runs off the async runtime
+async fn check_share_password(link: &ShareLink, password: String) -> Result<(), StatusCode> {
+    let Some(password_hash) = link.password.clone() else {
+        return Ok(());
+    };
+
+    if too_many_failures(link.id) {
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    let valid = tokio::task::spawn_blocking(move || verify_password(&password, &password_hash))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if !valid {
+        record_failure(link.id);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(())
+}
+
+/// Proof of having given the password of a link, kept in a cookie
+#[derive(Serialize, Deserialize)]
+struct ShareGrant {
+    share: i64,
+    aud: String,
+    exp: usize,
+}
+
+fn has_share_grant(link: &ShareLink, headers: &HeaderMap) -> bool {
+    let mut validation = Validation::default();
+    validation.set_audience(&[SHARE_GRANT_AUDIENCE]);
+
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .filter(|(name, _)| *name == SHARE_GRANT_COOKIE)
+        .any(|(_, value)| {
+            decode::<ShareGrant>(
+                value,
+                &DecodingKey::from_secret(jwt_secret().as_bytes()),
+                &validation,
+            )
+            .is_ok_and(|grant| grant.claims.share == link.id)
+        })
+}
+
+/// Look up an active link, checking its password if it has one
+async fn open_share(
+    pool: &PgPool,
+    token: &str,
+    headers: &HeaderMap,
+) -> Result<ShareLink, StatusCode> {
+    let link = get_active_share_link(pool, &hash_token(token))
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if link.password.is_some() && !has_share_grant(&link, headers) {
+        let password = headers
+            .get(SHARE_PASSWORD_HEADER)
+            .and_then(|h| h.to_str().ok())
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        check_share_password(&link, password.to_string()).await?;
+    }
+
+    Ok(link)
+}
+
+#[derive(Deserialize)]
+pub struct UnlockShareRequest {
+    pub password: String,
+}
+
+/// Password form for browsers, sets a cookie that opens the link for a while
+pub async fn unlock_share(
+    State(pool): State<PgPool>,
+    Path(token): Path<String>,
+    Form(request): Form<UnlockShareRequest>,
+) -> Result<Response, StatusCode> {
+    let link = get_active_share_link(&pool, &hash_token(&token))
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if link.password.is_none() {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    check_share_password(&link, request.password).await?;
+
+    let grant = ShareGrant {
+        share: link.id,
+        aud: SHARE_GRANT_AUDIENCE.to_string(),
+        exp: (Utc::now() + SHARE_GRANT_LIFETIME).timestamp() as usize,
+    };
+    let value = encode(
+        &Header::default(),
+        &grant,
+        &EncodingKey::from_secret(jwt_secret().as_bytes()),
+    )
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // Only sent back to this link's URLs
+    let cookie = format!(
+        "{SHARE_GRANT_COOKIE}={value}; Path=/s/{token}; Max-Age={}; HttpOnly; SameSite=Lax",
+        SHARE_GRANT_LIFETIME.num_seconds()
+    );
+
+    Ok((StatusCode::NO_CONTENT, [(header::SET_COOKIE, cookie)]).into_response())
+}
+
+/// An image reachable through the link. Album links only reach the album's
+/// own images, contributors' included, never the rest of the owner's access
+async fn shared_image(pool: &PgPool, link: &ShareLink, hash: &str) -> Result<Image, StatusCode> {
+    let image = match (&link.image_hash, link.album_id) {
+        (Some(image_hash), _) if image_hash == hash => get_image_by_hash(pool, hash, &link.owner)
+            .await
+            .map(|image| image.filter(|image| image.owner == link.owner)),
+        (None, Some(album_id)) => get_album_image(pool, album_id, hash).await,
+        _ => return Err(StatusCode::NOT_FOUND),
+    }
+    .map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    image
+        .filter(|image| image.deleted_at.is_none())
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Image metadata safe to show to anyone holding the link, no owner or location
+#[derive(Serialize)]
+pub struct SharedImageResponse {
+    pub hash: String,
+    pub extension: String,
+    pub image_name: Option<String>,
+    pub media_type: MediaType,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub captured_at: DateTime<Utc>,
+    pub description: Option<String>,
+}
+
+impl From<Image> for SharedImageResponse {
+    fn from(image: Image) -> Self {
+        SharedImageResponse {
+            captured_at: image.captured_at(),
+            hash: image.hash,
+            extension: image.extension,
+            image_name: image.image_name,
+            media_type: image.media_type,
+            width: image.width,
+            height: image.height,
+            description: image.description,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SharedAlbumResponse {
+    pub name: String,
+    pub image_count: i64,
+}
+
+#[derive(Serialize)]
+pub struct SharedResponse {
+    pub allow_download: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub album: Option<SharedAlbumResponse>, // None when a single image is shared
+    pub images: Vec<SharedImageResponse>,
+}
+
+#[derive(Deserialize)]
+pub struct SharedQuery {
+    #[serde(default)]
+    pub offset: i64,
+    pub limit: Option<i64>,
+}
+
+pub async fn get_shared(
+    State(pool): State<PgPool>,
+    Path(token): Path<String>,
+    Query(query): Query<SharedQuery>,
+    headers: HeaderMap,
+) -> Result<Json<SharedResponse>, StatusCode> {
+    let link = open_share(&pool, &token, &headers).await?;
+
+    let (album, images) = match (&link.image_hash, link.album_id) {
+        (Some(hash), _) => (None, vec![shared_image(&pool, &link, hash).await?]),
+        (None, Some(album_id)) => {
+            let album = get_album(&pool, album_id, &link.owner)
+                .await
+                .map_err(|e| {
+                    eprintln!("Database error: {:?}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?
+                .ok_or(StatusCode::NOT_FOUND)?;
+
+            let limit = query
+                .limit
+                .unwrap_or(DEFAULT_SHARED_IMAGES_LIMIT)
+                .clamp(1, MAX_SHARED_IMAGES_LIMIT);
+
+            let images = get_album_images(&pool, album_id, query.offset.max(0), limit)
+                .await
+                .map_err(|e| {
+                    eprintln!("Database error: {:?}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+
+            let album = SharedAlbumResponse {
+                name: album.name,
+                image_count: album.image_count,
+            };
+            (Some(album), images)
+        }
+        (None, None) => return Err(StatusCode::NOT_FOUND),
+    };
+
+    Ok(Json(SharedResponse {
+        allow_download: link.allow_download,
+        expires_at: link.expires_at,
+        album,
+        images: images.into_iter().map(SharedImageResponse::from).collect(),
+    }))
+}
+
+fn content_type(extension: &str) -> &'static str {
+    match extension
+        .trim_start_matches('.')
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "heic" | "heif" => "image/heic",
+        "avif" => "image/avif",
+        "tif" | "tiff" => "image/tiff",
+        "bmp" => "image/bmp",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn serve_shared_file(
+    pool: &PgPool,
+    token: &str,
+    hash: &str,
+    headers: &HeaderMap,
+    download: bool,
+) -> Result<Response, StatusCode> {
+    let link = open_share(pool, token, headers).await?;
+
+    // Without downloads only a preview can be viewed, never the original file
+    if download && !link.allow_download {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let image = shared_image(pool, &link, hash).await?;
+
+    let storage_path =
+        env::var("IMAGE_STORAGE_PATH").map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let file_name = format!("{}.{}", image.hash, image.extension.trim_start_matches('.'));
+    let file_path = PathBuf::from(&storage_path).join(&file_name);
+
+    let file_contents = tokio::fs::read(&file_path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    if !link.allow_download {
+        // Decoding is CPU bound, videos and other formats without a preview stay hidden
+        let preview = tokio::task::spawn_blocking(move || {
+            render_preview(&file_contents, SHARED_PREVIEW_SIZE)
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::FORBIDDEN)?;
+
+        return Ok((
+            [
+                (header::CONTENT_TYPE, "image/jpeg".to_string()),
+                (header::CONTENT_DISPOSITION, "inline".to_string()),
+            ],
+            preview,
+        )
+            .into_response());
+    }
+
+    let disposition = if download {
+        // Keep the header value plain ASCII without quotes
+        let name: String = image
+            .image_name
+            .unwrap_or(file_name)
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ' '))
+            .collect();
+        format!("attachment; filename=\"{}\"", name)
+    } else {
+        "inline".to_string()
+    };
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                content_type(&image.extension).to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        file_contents,
+    )
+        .into_response())
+}
+
+pub async fn get_shared_file(
+    State(pool): State<PgPool>,
+    Path((token, hash)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    serve_shared_file(&pool, &token, &hash, &headers, false).await
+}
+
+pub async fn download_shared_file(
+    State(pool): State<PgPool>,
+    Path((token, hash)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    serve_shared_file(&pool, &token, &hash, &headers, true).await
+}
@@ -0,0 +1,104 @@
+use crate::db::get_changes_since;
+use crate::routes::auth::Claims;
+use crate::types::{ImageChange, LibraryEvent};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::{Extension, Json, extract::Query, extract::State, http::StatusCode};
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::convert::Infallible;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+const DEFAULT_CHANGES_LIMIT: i64 = 500;
+const MAX_CHANGES_LIMIT: i64 = 5000;
+
+#[derive(Deserialize)]
+pub struct GetChangesQuery {
+    #[serde(default)]
+    pub since: i64,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct GetChangesResponse {
+    pub changes: Vec<ImageChange>,
+    pub cursor: i64, // pass as `since` on the next call
+    pub has_more: bool,
+}
+
+pub async fn get_changes(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<GetChangesQuery>,
+) -> Result<Json<GetChangesResponse>, StatusCode> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_CHANGES_LIMIT)
+        .clamp(1, MAX_CHANGES_LIMIT);
+
+    let changes = get_changes_since(&pool, &claims.sub, query.since, limit)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let has_more = changes.len() as i64 >= limit;
+    let cursor = changes.last().map_or(query.since, |c| c.cursor);
+
+    Ok(Json(GetChangesResponse {
+        changes: compact_changes(changes),
+        cursor,
+        has_more,
+    }))
+}
+
+/// Keep only the latest change per hash, the client only needs the final state
+fn compact_changes(changes: Vec<ImageChange>) -> Vec<ImageChange> {
+    // Entries of one transaction share a cursor, so go by position
+    let mut latest = std::collections::HashMap::new();
+    for (i, change) in changes.iter().enumerate() {
+        latest.insert(change.hash.clone(), i);
+    }
+
+    changes
+        .into_iter()
+        .enumerate()
+        .filter(|(i, c)| latest.get(&c.hash) == Some(i))
+        .map(|(_, c)| c)
+        .collect()
+}
+
+/// Server-Sent Events stream of the caller's library changes.
+/// Event ids are journal cursors, `resync` asks the client to call `/sync/changes`
+pub async fn change_events(
+    Extension(claims): Extension<Claims>,
+    Extension(events): Extension<broadcast::Sender<LibraryEvent>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = events.subscribe();
+
+    let stream =
+        futures_util::stream::unfold((receiver, claims.sub), |(mut receiver, owner)| async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(LibraryEvent::Changed {
+                        owner: change_owner,
+                        change,
+                    }) if change_owner == owner => Event::default()
+                        .event(change.kind.as_str())
+                        .id(change.cursor.to_string())
+                        .json_data(&change)
+                        .unwrap_or_default(),
+                    Ok(LibraryEvent::Changed { .. }) => continue,
+                    Ok(LibraryEvent::Resync) | Err(RecvError::Lagged(_)) => {
+                        Event::default().event("resync").data("")
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+
+                return Some((Ok(event), (receiver, owner)));
+            }
+        });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
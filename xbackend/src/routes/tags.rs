@@ -0,0 +1,126 @@
+use crate::db::{
+    add_image_tags, get_image_by_hash, get_image_tags, get_tag_counts, normalize_tag,
+    remove_image_tag,
+};
+use crate::routes::auth::Claims;
+use crate::types::TagCount;
+use axum::{Extension, Json, extract::Path, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+const MAX_TAGS_PER_REQUEST: usize = 100;
+
+#[derive(Serialize)]
+pub struct ImageTagsResponse {
+    pub tags: Vec<String>,
+}
+
+/// Tags belong to the image owner, images shared through an album are not tagged by others
+async fn check_image(pool: &PgPool, hash: &str, owner: &str) -> Result<(), StatusCode> {
+    get_image_by_hash(pool, hash, owner)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .filter(|image| image.owner == owner)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(())
+}
+
+pub async fn get_image_tags_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(hash): Path<String>,
+) -> Result<Json<ImageTagsResponse>, StatusCode> {
+    check_image(&pool, &hash, &claims.sub).await?;
+
+    let tags = get_image_tags(&pool, &hash, &claims.sub)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(ImageTagsResponse { tags }))
+}
+
+#[derive(Deserialize)]
+pub struct AddTagsRequest {
+    pub tags: Vec<String>,
+}
+
+pub async fn add_image_tags_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(hash): Path<String>,
+    Json(request): Json<AddTagsRequest>,
+) -> Result<Json<ImageTagsResponse>, StatusCode> {
+    if request.tags.is_empty() || request.tags.len() > MAX_TAGS_PER_REQUEST {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let tags = request
+        .tags
+        .iter()
+        .map(|t| normalize_tag(t))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    check_image(&pool, &hash, &claims.sub).await?;
+
+    add_image_tags(&pool, &hash, &claims.sub, &tags)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let tags = get_image_tags(&pool, &hash, &claims.sub)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(ImageTagsResponse { tags }))
+}
+
+pub async fn remove_image_tag_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path((hash, tag)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let tag = normalize_tag(&tag).ok_or(StatusCode::NOT_FOUND)?;
+
+    let removed = remove_image_tag(&pool, &hash, &claims.sub, &tag)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if removed {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetTagsResponse {
+    pub tags: Vec<TagCount>,
+}
+
+pub async fn get_tags(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<GetTagsResponse>, StatusCode> {
+    let tags = get_tag_counts(&pool, &claims.sub).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(GetTagsResponse { tags }))
+}
@@ -0,0 +1,83 @@
+use crate::db::{get_timeline, is_valid_timezone, normalize_tag};
+use crate::routes::auth::Claims;
+use crate::types::{ImageFilter, MediaType, TimelineBucket, TimelineGranularity};
+use axum::{Extension, Json, extract::Query, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+#[derive(Deserialize)]
+pub struct TimelineQuery {
+    #[serde(default)]
+    pub granularity: TimelineGranularity,
+    pub tz: Option<String>, // IANA name, UTC by default
+    pub album_id: Option<i64>,
+    pub owner: Option<String>,
+    pub tag: Option<String>,
+    pub has_location: Option<bool>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+    pub media_type: Option<MediaType>,
+}
+
+#[derive(Serialize)]
+pub struct TimelineResponse {
+    pub granularity: TimelineGranularity,
+    pub tz: String,
+    pub buckets: Vec<TimelineBucket>,
+}
+
+/// Parse and check a `tz` parameter, defaulting to UTC
+pub async fn resolve_timezone(pool: &PgPool, tz: Option<String>) -> Result<String, StatusCode> {
+    let tz = tz.unwrap_or_else(|| "UTC".to_string());
+
+    let valid = is_valid_timezone(pool, &tz).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if valid {
+        Ok(tz)
+    } else {
+        Err(StatusCode::BAD_REQUEST)
+    }
+}
+
+pub async fn get_timeline_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<TimelineQuery>,
+) -> Result<Json<TimelineResponse>, StatusCode> {
+    let tz = resolve_timezone(&pool, query.tz).await?;
+
+    let filter = ImageFilter {
+        album_id: query.album_id,
+        owner: query.owner,
+        tags: query
+            .tag
+            .as_deref()
+            .map(|t| normalize_tag(t).ok_or(StatusCode::BAD_REQUEST))
+            .transpose()?
+            .into_iter()
+            .collect(),
+        has_location: query.has_location,
+        country: query.country,
+        region: query.region,
+        city: query.city,
+        media_type: query.media_type,
+        ..Default::default()
+    };
+
+    let buckets = get_timeline(&pool, &claims.sub, &filter, query.granularity, &tz)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(TimelineResponse {
+        granularity: query.granularity,
+        tz,
+        buckets,
+    }))
+}
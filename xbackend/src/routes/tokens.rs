@@ -0,0 +1,94 @@
+use crate::db::{create_api_token, get_api_tokens, revoke_api_token};
+use crate::routes::auth::{API_TOKEN_PREFIX, Claims};
+use crate::secret::{generate_token, hash_token};
+use crate::types::ApiToken;
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub device_id: i64,
+}
+
+#[derive(Serialize)]
+pub struct CreateTokenResponse {
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+    pub token: String, // only returned here, the server keeps a hash
+}
+
+pub async fn create_token_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Json(request): Json<CreateTokenRequest>,
+) -> Result<(StatusCode, Json<CreateTokenResponse>), StatusCode> {
+    if request.name.trim().is_empty() || request.name.len() > 255 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let token = format!("{API_TOKEN_PREFIX}{}", generate_token());
+
+    let api_token = create_api_token(
+        &pool,
+        &claims.sub,
+        request.device_id,
+        request.name.trim(),
+        &hash_token(&token),
+    )
+    .await
+    .map_err(|e| match e {
+        // The device is not one of the caller's
+        sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() => StatusCode::NOT_FOUND,
+        e => {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateTokenResponse { api_token, token }),
+    ))
+}
+
+#[derive(Serialize)]
+pub struct GetTokensResponse {
+    pub tokens: Vec<ApiToken>,
+}
+
+pub async fn get_tokens_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<GetTokensResponse>, StatusCode> {
+    let tokens = get_api_tokens(&pool, &claims.sub).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(GetTokensResponse { tokens }))
+}
+
+pub async fn revoke_token_endpoint(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+    let revoked = revoke_api_token(&pool, id, &claims.sub)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if revoked {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
@@ -0,0 +1,277 @@
+use crate::db::exceeds_quota;
+use crate::img::{ContentHasher, METADATA_HEAD_SIZE};
+use crate::routes::auth::Claims;
+use crate::routes::image::{NewUpload, SourceFields, register_upload};
+use crate::types::MediaType;
+use axum::extract::multipart::Field;
+use axum::{Extension, Json, extract::Multipart, extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{env, path::Path, path::PathBuf};
+use tokio::io::AsyncWriteExt;
+
+const MAX_FILE_SIZE: usize = 512 * 1024 * 1024;
+/// Metadata parts are buffered, the request body itself is unbounded
+const MAX_METADATA_SIZE: usize = 64 * 1024;
+/// With the per-file limit this bounds a request, a metadata part per file included
+const MAX_UPLOAD_PARTS: usize = 200;
+
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Optional `metadata` part, applies to the `file` part that follows it
+#[derive(Deserialize, Default)]
+pub struct FileMetadata {
+    pub image_name: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub modified_at: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub source: SourceFields,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadStatus {
+    Created,
+    Duplicate,
+    Rejected,
+}
+
+#[derive(Serialize)]
+pub struct UploadFileResult {
+    pub file_name: String,
+    pub status: UploadStatus,
+    pub hash: Option<String>,
+    pub reason: Option<&'static str>,
+}
+
+impl UploadFileResult {
+    fn rejected(file_name: String, reason: &'static str) -> Self {
+        UploadFileResult {
+            file_name,
+            status: UploadStatus::Rejected,
+            hash: None,
+            reason: Some(reason),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct UploadImagesResponse {
+    pub files: Vec<UploadFileResult>,
+}
+
+pub async fn upload_images(
+    State(pool): State<PgPool>,
+    Extension(claims): Extension<Claims>,
+    mut multipart: Multipart,
+) -> Result<Json<UploadImagesResponse>, StatusCode> {
+    let storage_path =
+        env::var("IMAGE_STORAGE_PATH").map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut files = Vec::new();
+    let mut metadata: Option<Result<FileMetadata, serde_json::Error>> = None;
+    let mut parts = 0;
+
+    // Parts are processed one at a time as they arrive
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+    {
+        parts += 1;
+        if parts > MAX_UPLOAD_PARTS {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        match field.name() {
+            Some("metadata") => {
+                let mut bytes = Vec::new();
+                while let Some(chunk) = field.chunk().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+                    if bytes.len() + chunk.len() > MAX_METADATA_SIZE {
+                        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+                    }
+                    bytes.extend_from_slice(&chunk);
+                }
+                metadata = Some(serde_json::from_slice(&bytes));
+            }
+            Some("file") => {
+                let file_name = field.file_name().unwrap_or_default().to_string();
+
+                let result = match metadata.take() {
+                    Some(Err(_)) => UploadFileResult::rejected(file_name, "invalid metadata"),
+                    Some(Ok(metadata)) => {
+                        store_file(&pool, &claims, &storage_path, field, metadata).await?
+                    }
+                    None => {
+                        store_file(&pool, &claims, &storage_path, field, Default::default()).await?
+                    }
+                };
+
+                files.push(result);
+            }
+            // Unknown parts are skipped
+            _ => continue,
+        }
+    }
+
+    Ok(Json(UploadImagesResponse { files }))
+}
+
+async fn store_file(
+    pool: &PgPool,
+    claims: &Claims,
+    storage_path: &str,
+    mut field: Field<'_>,
+    metadata: FileMetadata,
+) -> Result<UploadFileResult, StatusCode> {
+    let owner = &claims.sub;
+    let file_name = field.file_name().unwrap_or_default().to_string();
+
+    let extension = match Path::new(&file_name).extension().and_then(|e| e.to_str()) {
+        Some(extension) => extension.to_ascii_lowercase(),
+        None => return Ok(UploadFileResult::rejected(file_name, "missing extension")),
+    };
+
+    if MediaType::from_extension(&extension) == MediaType::Other {
+        return Ok(UploadFileResult::rejected(file_name, "unsupported type"));
+    }
+
+    let source = match metadata.source.resolve(pool, claims).await {
+        Ok(source) => source,
+        Err(StatusCode::BAD_REQUEST) => {
+            return Ok(UploadFileResult::rejected(file_name, "invalid source"));
+        }
+        Err(status) => return Err(status),
+    };
+
+    // Stream into a temporary file, it is renamed once the hash is known
+    let temp_path = PathBuf::from(storage_path).join(format!(
+        ".upload-{}-{}",
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let (hash, head, size) = match write_field(&mut field, &temp_path).await {
+        Ok(Some(written)) => written,
+        other => {
+            if let Err(e) = tokio::fs::remove_file(&temp_path).await {
+                eprintln!(
+                    "Warning: Could not delete file {}: {:?}",
+                    temp_path.display(),
+                    e
+                );
+            }
+
+            return match other {
+                Ok(_) => Ok(UploadFileResult::rejected(file_name, "file too large")),
+                Err(status) => Err(status),
+            };
+        }
+    };
+
+    let over_quota = exceeds_quota(pool, owner, &hash, size as i64)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if over_quota {
+        if let Err(e) = tokio::fs::remove_file(&temp_path).await {
+            eprintln!(
+                "Warning: Could not delete file {}: {:?}",
+                temp_path.display(),
+                e
+            );
+        }
+
+        return Ok(UploadFileResult::rejected(file_name, "quota exceeded"));
+    }
+
+    let file_path = PathBuf::from(storage_path).join(format!("{}.{}", hash, extension));
+
+    tokio::fs::rename(&temp_path, &file_path)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let now = Utc::now();
+    let upload = NewUpload {
+        hash,
+        extension,
+        owner: owner.to_string(),
+        image_name: metadata.image_name.or_else(|| Some(file_name.clone())),
+        created_at: metadata.created_at.unwrap_or(now),
+        modified_at: metadata.modified_at.unwrap_or(now),
+        source,
+    };
+
+    // The file stays stored under its hash, a retry picks it up
+    let (image, created) = match register_upload(pool, upload, &head, size as i64).await {
+        Ok(Some(registered)) => registered,
+        Ok(None) => {
+            if let Err(e) = tokio::fs::remove_file(&file_path).await {
+                eprintln!(
+                    "Warning: Could not delete file {}: {:?}",
+                    file_path.display(),
+                    e
+                );
+            }
+
+            return Ok(UploadFileResult::rejected(file_name, "quota exceeded"));
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return Ok(UploadFileResult::rejected(file_name, "could not be stored"));
+        }
+    };
+
+    Ok(UploadFileResult {
+        file_name,
+        status: if created {
+            UploadStatus::Created
+        } else {
+            UploadStatus::Duplicate
+        },
+        hash: Some(image.hash),
+        reason: None,
+    })
+}
+
+/// Write the part to `path` chunk by chunk, returns its hash, the start of the
+/// file for metadata extraction and its size, or `None` if it exceeds the size limit
+async fn write_field(
+    field: &mut Field<'_>,
+    path: &Path,
+) -> Result<Option<(String, Vec<u8>, usize)>, StatusCode> {
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut hasher = ContentHasher::default();
+    let mut head = Vec::new();
+    let mut size = 0;
+
+    while let Some(chunk) = field.chunk().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+        size += chunk.len();
+        if size > MAX_FILE_SIZE {
+            return Ok(None);
+        }
+
+        hasher.update(&chunk);
+        if head.len() < METADATA_HEAD_SIZE {
+            let take = chunk.len().min(METADATA_HEAD_SIZE - head.len());
+            head.extend_from_slice(&chunk[..take]);
+        }
+        file.write_all(&chunk)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+
+    file.flush()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Some((hasher.finalize(), head, size)))
+}
@@ -0,0 +1,47 @@
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+    password_hash::{
+        self, SaltString,
+        rand_core::{OsRng, RngCore},
+    },
+};
+use base64::{Engine as _, engine::general_purpose};
+
+/// Hash a password using Argon2id with custom parameters
+pub fn hash_password(password: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    let params = Params::new(65536, 2, 2, None)?;
+
+    let argon2 = Argon2::new(
+        Algorithm::Argon2id, // Use Argon2id variant (recommended)
+        Version::V0x13,      // Use version 1.3
+        params,
+    );
+
+    Ok(argon2
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Check a password against a stored Argon2 hash, the parameters come from the hash
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, password_hash::Error> {
+    let parsed_hash = PasswordHash::new(hash)?;
+
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok())
+}
+
+/// Random URL-safe token with 256 bits of entropy
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Tokens are stored hashed, they are random enough not to need a salt
+pub fn hash_token(token: &str) -> String {
+    blake3::hash(token.as_bytes()).to_hex().to_string()
+}
@@ -1,3 +1,10 @@
+#[allow(clippy::module_inception)]
 mod types;
 
-pub use types::{Image, User, UserCredentials};
+pub use types::{
+    Album, AlbumMember, AlbumRole, ApiToken, BatchOperation, BoundingBox, ChangeKind, CursorKey,
+    Device, DeviceFile, Event, Image, ImageChange, ImageCursor, ImageFilter, ImageSort,
+    ImageSource, ImageUpdate, LibraryEvent, Location, MapCluster, MediaType, PartnerShare, Place,
+    PlaceGroup, PlaceLevel, RegistrationMode, Session, ShareLink, ShareTarget, SyncStatus,
+    TagCount, TimelineBucket, TimelineGranularity, User, UserCredentials,
+};
@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
@@ -14,6 +14,27 @@ pub struct User {
     #[serde(skip_serializing)]
     pub password: String,
     pub created_at: Option<chrono::NaiveDateTime>,
+    pub is_admin: bool,
+}
+
+/// How `POST /register` behaves, from the `REGISTRATION` variable
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RegistrationMode {
+    #[default]
+    Disabled,
+    Open,
+    Invite, // a single-use code from an admin is needed
+}
+
+impl RegistrationMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "disabled" => Some(RegistrationMode::Disabled),
+            "open" => Some(RegistrationMode::Open),
+            "invite" => Some(RegistrationMode::Invite),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -26,4 +47,509 @@ pub struct Image {
     pub modified_at: DateTime<Utc>,
     pub longitude: Option<f64>,
     pub latitude: Option<f64>,
+    pub media_type: MediaType,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub taken_at: Option<DateTime<Utc>>,
+    pub camera_model: Option<String>,
+    pub description: Option<String>,
+    pub taken_at_override: Option<DateTime<Utc>>,
+    pub manual_latitude: Option<f64>,
+    pub manual_longitude: Option<f64>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub favorite: bool,
+    pub rating: Option<i16>,
+    pub place: Option<Place>,
+    pub edited_at: Option<DateTime<Utc>>, // last metadata edit, `None` if never edited
+}
+
+impl Image {
+    /// Capture time, mirrors the generated `captured_at` column
+    pub fn captured_at(&self) -> DateTime<Utc> {
+        self.taken_at_override
+            .or(self.taken_at)
+            .unwrap_or(self.created_at)
+    }
+}
+
+/// Metadata edit, `None` leaves a field untouched and `Some(None)` clears it
+#[derive(Debug, Clone, Default)]
+pub struct ImageUpdate {
+    pub image_name: Option<String>,
+    pub description: Option<Option<String>>,
+    pub taken_at: Option<Option<DateTime<Utc>>>,
+    pub location: Option<Option<Location>>,
+    pub favorite: Option<bool>,
+    pub rating: Option<Option<i16>>,
+}
+
+/// Operation applied to every image of a batch
+#[derive(Debug, Clone)]
+pub enum BatchOperation {
+    Delete,
+    Trash,
+    Restore,
+    AddToAlbum(i64),
+    Update(ImageUpdate),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Location {
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.latitude) && (-180.0..=180.0).contains(&self.longitude)
+    }
+}
+
+/// Named place resolved from coordinates by the offline gazetteer
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Place {
+    pub country: String,
+    pub region: Option<String>,
+    pub city: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaceLevel {
+    Country,
+    Region,
+    #[default]
+    City,
+}
+
+impl PlaceLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlaceLevel::Country => "country",
+            PlaceLevel::Region => "region",
+            PlaceLevel::City => "city",
+        }
+    }
+}
+
+/// Images grouped by place, down to the requested level
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct PlaceGroup {
+    pub country: String,
+    pub region: Option<String>,
+    pub city: Option<String>,
+    pub count: i64,
+    pub hash: String, // most recent image of the group
+}
+
+/// Geographic area, `min_longitude > max_longitude` when it crosses the antimeridian
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BoundingBox {
+    pub min_latitude: f64,
+    pub min_longitude: f64,
+    pub max_latitude: f64,
+    pub max_longitude: f64,
+}
+
+impl BoundingBox {
+    pub fn is_valid(&self) -> bool {
+        let corners = [
+            Location {
+                latitude: self.min_latitude,
+                longitude: self.min_longitude,
+            },
+            Location {
+                latitude: self.max_latitude,
+                longitude: self.max_longitude,
+            },
+        ];
+
+        corners.iter().all(Location::is_valid) && self.min_latitude <= self.max_latitude
+    }
+
+    /// The box as longitude ranges that don't wrap around
+    pub fn longitude_ranges(&self) -> Vec<(f64, f64)> {
+        if self.min_longitude <= self.max_longitude {
+            vec![(self.min_longitude, self.max_longitude)]
+        } else {
+            vec![(self.min_longitude, 180.0), (-180.0, self.max_longitude)]
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Deleted,
+    Updated,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Deleted => "deleted",
+            ChangeKind::Updated => "updated",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "added" => Some(ChangeKind::Added),
+            "deleted" => Some(ChangeKind::Deleted),
+            "updated" => Some(ChangeKind::Updated),
+            _ => None,
+        }
+    }
+}
+
+/// A single entry of the per-user change journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageChange {
+    pub cursor: i64,
+    pub hash: String,
+    pub kind: ChangeKind,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Broadcast to the connected clients as journal entries are committed
+#[derive(Debug, Clone)]
+pub enum LibraryEvent {
+    Changed {
+        owner: String,
+        change: ImageChange,
+    },
+    /// Notifications may have been missed, clients should catch up via the journal
+    Resync,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaType {
+    Image,
+    Video,
+    Other,
+}
+
+impl MediaType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaType::Image => "image",
+            MediaType::Video => "video",
+            MediaType::Other => "other",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "image" => Some(MediaType::Image),
+            "video" => Some(MediaType::Video),
+            "other" => Some(MediaType::Other),
+            _ => None,
+        }
+    }
+
+    /// Classify a file by its extension, with or without the leading dot
+    pub fn from_extension(extension: &str) -> Self {
+        match extension
+            .trim_start_matches('.')
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "jpg" | "jpeg" | "png" | "gif" | "webp" | "heic" | "heif" | "avif" | "tif" | "tiff"
+            | "bmp" | "dng" => MediaType::Image,
+            "mp4" | "mov" | "m4v" | "3gp" | "avi" | "mkv" | "webm" => MediaType::Video,
+            _ => MediaType::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageSort {
+    #[default]
+    Newest,
+    Oldest,
+    NameAsc,
+    NameDesc,
+    RatingDesc, // unrated images last
+}
+
+/// Filters shared by the image listing queries
+#[derive(Debug, Default)]
+pub struct ImageFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub media_type: Option<MediaType>,
+    pub trashed: bool,     // list the trash instead of the library
+    pub tags: Vec<String>, // images having all of them
+    pub favorite: Option<bool>,
+    pub min_rating: Option<i16>,
+    pub name: Option<String>,
+    pub camera_model: Option<String>,
+    pub has_location: Option<bool>,
+    pub bbox: Option<BoundingBox>,
+    pub album_id: Option<i64>,
+    pub owner: Option<String>, // images of this user only, e.g. a partner's library
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CursorKey {
+    Time(DateTime<Utc>),
+    Text(String),
+    Number(i64),
+}
+
+/// Position after the last returned image, `key` is its sort value
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageCursor {
+    pub key: CursorKey,
+    pub hash: String,
+}
+
+impl ImageSort {
+    pub fn cursor_for(&self, image: &Image) -> ImageCursor {
+        let key = match self {
+            ImageSort::Newest | ImageSort::Oldest => CursorKey::Time(image.captured_at()),
+            ImageSort::NameAsc | ImageSort::NameDesc => {
+                CursorKey::Text(image.image_name.clone().unwrap_or_default())
+            }
+            ImageSort::RatingDesc => CursorKey::Number(image.rating.unwrap_or(0).into()),
+        };
+
+        ImageCursor {
+            key,
+            hash: image.hash.clone(),
+        }
+    }
+
+    /// Whether a cursor produced by some sort order can continue this one
+    pub fn accepts(&self, cursor: &ImageCursor) -> bool {
+        matches!(
+            (self, &cursor.key),
+            (ImageSort::Newest | ImageSort::Oldest, CursorKey::Time(_))
+                | (ImageSort::NameAsc | ImageSort::NameDesc, CursorKey::Text(_))
+                | (ImageSort::RatingDesc, CursorKey::Number(_))
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Device {
+    pub id: i64,
+    pub name: String,
+    pub platform: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub last_sync_at: Option<DateTime<Utc>>,
+    pub last_sync_status: Option<SyncStatus>,
+    pub last_sync_cursor: Option<i64>,
+    pub last_success_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncStatus {
+    Succeeded,
+    Failed,
+}
+
+impl SyncStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncStatus::Succeeded => "succeeded",
+            SyncStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "succeeded" => Some(SyncStatus::Succeeded),
+            "failed" => Some(SyncStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// Where an upload came from on the client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageSource {
+    pub device_id: i64,
+    pub relative_path: String,
+    pub source_folder: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceFile {
+    pub hash: String,
+    pub relative_path: String,
+    pub source_folder: Option<String>,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Album {
+    pub id: i64,
+    pub role: AlbumRole, // of the caller
+    pub owner: String,
+    pub name: String,
+    pub cover_hash: Option<String>,
+    pub image_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub modified_at: DateTime<Utc>,
+}
+
+/// A tag in the owner's vocabulary
+#[derive(Debug, Clone, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: i64,
+}
+
+/// Geotagged images grouped into one map marker
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct MapCluster {
+    pub latitude: f64,  // centroid
+    pub longitude: f64, // centroid
+    pub count: i64,
+    pub hash: String, // most recent image of the cluster
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimelineGranularity {
+    Year,
+    #[default]
+    Month,
+    Day,
+}
+
+impl TimelineGranularity {
+    /// Field name understood by `date_trunc`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimelineGranularity::Year => "year",
+            TimelineGranularity::Month => "month",
+            TimelineGranularity::Day => "day",
+        }
+    }
+}
+
+/// Number of images captured in the period starting at `date`, in the caller's timezone
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct TimelineBucket {
+    pub date: NaiveDate,
+    pub count: i64,
+}
+
+/// Public link to one image or one album
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareLink {
+    pub id: i64,
+    pub owner: String,
+    pub image_hash: Option<String>,
+    pub album_id: Option<i64>,
+    #[serde(skip_serializing)]
+    pub password: Option<String>, // Argon2 hash
+    pub allow_download: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// What a new share link points at
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShareTarget {
+    Image(String),
+    Album(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlbumRole {
+    Owner,
+    Contributor,
+    Viewer,
+}
+
+impl AlbumRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlbumRole::Owner => "owner",
+            AlbumRole::Contributor => "contributor",
+            AlbumRole::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "owner" => Some(AlbumRole::Owner),
+            "contributor" => Some(AlbumRole::Contributor),
+            "viewer" => Some(AlbumRole::Viewer),
+            _ => None,
+        }
+    }
+
+    pub fn is_owner(&self) -> bool {
+        *self == AlbumRole::Owner
+    }
+
+    /// Whether the role allows adding images to the album
+    pub fn can_add(&self) -> bool {
+        matches!(self, AlbumRole::Owner | AlbumRole::Contributor)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlbumMember {
+    pub username: String,
+    pub role: AlbumRole,
+    pub added_at: DateTime<Utc>,
+}
+
+/// Read access to the whole library of `owner`, granted to `partner`
+#[derive(Debug, Clone, Serialize)]
+pub struct PartnerShare {
+    pub owner: String,
+    pub partner: String,
+    pub since: Option<DateTime<Utc>>, // only images captured from then on
+    pub in_timeline: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Photos grouped automatically, shown as a read-only album
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub id: i64,
+    pub title: String,         // suggested, e.g. "Weekend in Lisbon"
+    pub place: Option<String>, // the place the title names
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub image_count: i64,
+    pub cover_hash: Option<String>,
+}
+
+/// A login on one device, see `POST /refresh`
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub id: i64,
+    pub device_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Token for a sync client, acting for one of the user's devices
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub device_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
 }